@@ -1,4 +1,8 @@
-use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
 pub struct TokenResponse {
@@ -6,31 +10,633 @@ pub struct TokenResponse {
     // token_type: String,
     pub expires_in: u64,
     pub refresh_token: String,
-    // scope: String,
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+/// Account of the token owner, from the `user-info` endpoint.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    pub user_id: String,
+    pub user_name: String,
+    #[serde(default)]
+    pub user_email: Option<String>,
+}
+
+/// Unread items per stream, from the `unread-count` endpoint.
+#[derive(Debug, Deserialize)]
+pub struct UnreadCounts {
+    /// Counts are capped at this number
+    #[serde(deserialize_with = "u64_from_string_or_number")]
+    pub max: u64,
+    pub unreadcounts: Vec<UnreadCount>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnreadCount {
+    pub id: String,
+    #[serde(deserialize_with = "u64_from_string_or_number")]
+    pub count: u64,
+    #[serde(default, deserialize_with = "u64_from_string_or_number")]
+    pub newest_item_timestamp_usec: u64,
 }
 
+impl UnreadCounts {
+    /// The unread count of `stream`, or 0 if it is not listed.
+    pub fn of(&self, stream: &StreamId) -> u64 {
+        self.unreadcounts
+            .iter()
+            .find(|count| count.id.parse::<StreamId>().as_ref() == Ok(stream))
+            .map_or(0, |count| count.count)
+    }
+
+    /// `count`, or `1000+` when it reached the cap.
+    pub fn describe(&self, count: u64) -> String {
+        match count >= self.max && self.max > 0 {
+            true => format!("{}+", self.max),
+            false => count.to_string(),
+        }
+    }
+}
+
+/// Saved tokens of a profile. `expires_in` is the expiry time as a Unix timestamp.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default)]
     pub authorization_code: String,
+    #[serde(default)]
     pub state: String,
     pub access_token: String,
     pub refresh_token: String,
     pub expires_in: u64,
+    // Scope が保存されていない古いファイルは read のみとして扱う
+    #[serde(default = "Config::default_scope")]
+    pub scope: String,
 }
 
-#[derive(Deserialize)]
+impl Config {
+    fn default_scope() -> String {
+        Scope::Read.as_str().to_string()
+    }
+}
+
+/// OAuth scope requested from Inoreader. `write` always implies `read`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    Read,
+    ReadWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::ReadWrite => "read write",
+        }
+    }
+
+    pub fn includes(&self, required: Scope) -> bool {
+        *self == Scope::ReadWrite || required == Scope::Read
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut scope = None;
+        for part in s.split([' ', ',', '+']) {
+            match part {
+                "" => {}
+                "read" => scope = scope.or(Some(Scope::Read)),
+                "write" => scope = Some(Scope::ReadWrite),
+                other => return Err(format!("unknown scope '{}'", other)),
+            }
+        }
+        scope.ok_or_else(|| "scope must not be empty".to_string())
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Where the tokens of a profile are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialStoreKind {
+    /// Plaintext, inside the profile's section of the config file
+    File,
+    /// OS secret service (Secret Service on Linux, Keychain on macOS, Credential Manager on Windows)
+    Keyring,
+    /// AES-256-GCM encrypted file, key derived from a passphrase with Argon2id
+    Encrypted,
+}
+
+impl FromStr for CredentialStoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(CredentialStoreKind::File),
+            "keyring" => Ok(CredentialStoreKind::Keyring),
+            "encrypted" => Ok(CredentialStoreKind::Encrypted),
+            other => Err(format!(
+                "unknown credential store '{}' (expected file, keyring or encrypted)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CredentialStoreKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialStoreKind::File => f.write_str("file"),
+            CredentialStoreKind::Keyring => f.write_str("keyring"),
+            CredentialStoreKind::Encrypted => f.write_str("encrypted"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Csv,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "articles.csv",
+            OutputFormat::Json => "articles.json",
+        }
+    }
+
+    pub fn default_archive_path(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "archive.csv",
+            OutputFormat::Json => "archive.jsonl",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(format!(
+                "unknown output format '{}' (expected csv or json)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Csv => f.write_str("csv"),
+            OutputFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
+/// A named account: its client credentials, where its tokens live and its output defaults.
+/// Unset client credentials fall back to the `INOREADER_*` environment variables.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub credential_store: CredentialStoreKind,
+    pub output_format: OutputFormat,
+    pub output_path: Option<String>,
+}
+
+impl Profile {
+    pub fn new(name: &str, credential_store: CredentialStoreKind) -> Self {
+        Profile {
+            name: name.to_string(),
+            client_id: None,
+            client_secret: None,
+            redirect_uri: None,
+            credential_store,
+            output_format: OutputFormat::Csv,
+            output_path: None,
+        }
+    }
+
+    pub fn output_path(&self) -> &str {
+        self.output_path
+            .as_deref()
+            .unwrap_or(self.output_format.default_path())
+    }
+}
+
+// RFC 3986 unreserved 以外はすべてエンコードする（ID 中の `/` や `:` も含む）
+const STREAM_ID_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+const STATE_PREFIX: &str = "state/com.google/";
+
+/// A stream of items: a system state, a label (folder or tag) or a single feed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamId {
+    ReadingList,
+    Starred,
+    Read,
+    Annotated,
+    Broadcast,
+    Label(String),
+    Feed(String),
+}
+
+impl StreamId {
+    /// The stream ID, e.g. `user/-/state/com.google/starred` or `feed/http://example.com/rss`.
+    pub fn as_id(&self) -> String {
+        match self {
+            StreamId::Label(name) => format!("user/-/label/{}", name),
+            StreamId::Feed(url) => format!("feed/{}", url),
+            state => format!("user/-/{}{}", STATE_PREFIX, state.state_name().unwrap()),
+        }
+    }
+
+    /// The stream ID percent-encoded for use as a URL path segment.
+    pub fn encoded(&self) -> String {
+        utf8_percent_encode(&self.as_id(), STREAM_ID_ENCODE_SET).to_string()
+    }
+
+    fn state_name(&self) -> Option<&'static str> {
+        match self {
+            StreamId::ReadingList => Some("reading-list"),
+            StreamId::Starred => Some("starred"),
+            StreamId::Read => Some("read"),
+            StreamId::Annotated => Some("annotated"),
+            StreamId::Broadcast => Some("broadcast"),
+            StreamId::Label(_) | StreamId::Feed(_) => None,
+        }
+    }
+
+    fn from_state_name(name: &str) -> Option<Self> {
+        [
+            StreamId::ReadingList,
+            StreamId::Starred,
+            StreamId::Read,
+            StreamId::Annotated,
+            StreamId::Broadcast,
+        ]
+        .into_iter()
+        .find(|state| state.state_name() == Some(name))
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = String;
+
+    /// Accepts a full stream ID (`user/-/label/Tech`, `feed/http://...`, `user/1005921515/state/com.google/read`)
+    /// or the short name of a system state (`starred`, `reading-list`, ...).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(url) = s.strip_prefix("feed/") {
+            return Ok(StreamId::Feed(url.to_string()));
+        }
+        if let Some((_, rest)) = s
+            .strip_prefix("user/")
+            .and_then(|rest| rest.split_once('/'))
+        {
+            if let Some(name) = rest.strip_prefix("label/") {
+                return Ok(StreamId::Label(name.to_string()));
+            }
+            if let Some(name) = rest.strip_prefix(STATE_PREFIX) {
+                return Self::from_state_name(name)
+                    .ok_or_else(|| format!("unsupported stream state '{}'", name));
+            }
+        }
+        Self::from_state_name(s).ok_or_else(|| {
+            format!(
+                "unknown stream '{}' (expected reading-list, starred, read, annotated, broadcast, user/-/label/<name> or feed/<url>)",
+                s
+            )
+        })
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.as_id())
+    }
+}
+
+/// A parsed page of `stream/contents`.
+#[derive(Debug)]
 pub struct ApiResponse {
     pub items: Vec<Item>,
     pub continuation: Option<String>,
+    /// Items that were skipped or kept with missing data
+    pub warnings: Vec<ItemWarning>,
 }
 
-#[derive(Deserialize)]
+/// Problem with one item of a page.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemWarning {
+    /// Position of the item in its page
+    pub index: usize,
+    pub id: Option<String>,
+    pub issue: ItemIssue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItemIssue {
+    /// The item could not be read and was left out
+    Skipped(String),
+    MissingTitle,
+    /// No canonical, alternate or origin URL
+    MissingUrl,
+}
+
+impl ItemWarning {
+    pub fn is_skipped(&self) -> bool {
+        matches!(self.issue, ItemIssue::Skipped(_))
+    }
+}
+
+impl fmt::Display for ItemWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "item {}", self.index)?;
+        if let Some(id) = &self.id {
+            write!(f, " ({})", id)?;
+        }
+        match &self.issue {
+            ItemIssue::Skipped(e) => write!(f, ": skipped, {}", e),
+            ItemIssue::MissingTitle => write!(f, ": no title"),
+            ItemIssue::MissingUrl => write!(f, ": no URL"),
+        }
+    }
+}
+
+/// An article of a stream, as returned by `stream/contents`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Item {
+    pub id: String,
+    #[serde(default, deserialize_with = "string_or_null")]
     pub title: String,
+    /// Unix time (seconds)
+    #[serde(default)]
+    pub published: u64,
+    #[serde(default)]
+    pub updated: u64,
+    #[serde(default, deserialize_with = "u64_from_string_or_number")]
+    pub crawl_time_msec: u64,
+    #[serde(default, deserialize_with = "u64_from_string_or_number")]
+    pub timestamp_usec: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Stream IDs the item belongs to: states (read, starred, ...) and labels
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
     pub canonical: Vec<Link>,
-    // alternate: Vec<Link>  // 必要に応じて
+    #[serde(default)]
+    pub alternate: Vec<Link>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enclosure: Vec<Enclosure>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Content>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<Content>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
 }
 
-#[derive(Deserialize)]
+impl Item {
+    /// The article URL: canonical, else alternate, else the site of the feed.
+    pub fn url(&self) -> Option<&str> {
+        let links = self.canonical.iter().chain(&self.alternate);
+        links
+            .map(|link| link.href.as_str())
+            .chain(self.origin.as_ref().map(|origin| origin.html_url.as_str()))
+            .find(|url| !url.is_empty())
+    }
+
+    /// Names of the labels (folders and tags) of the item.
+    pub fn labels(&self) -> Vec<&str> {
+        self.categories
+            .iter()
+            .filter_map(|category| category.split_once("/label/").map(|(_, name)| name))
+            .collect()
+    }
+
+    pub fn feed_title(&self) -> Option<&str> {
+        self.origin.as_ref().map(|origin| origin.title.as_str())
+    }
+}
+
+fn string_or_null<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+// crawlTimeMsec / timestampUsec は文字列で返ってくる
+fn u64_from_string_or_number<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(u64),
+    }
+
+    match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::String(s) => s.parse().map_err(serde::de::Error::custom),
+        StringOrNumber::Number(n) => Ok(n),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Link {
     pub href: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Enclosure {
+    pub href: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "u64_from_string_or_number",
+        skip_serializing_if = "is_zero"
+    )]
+    pub length: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Content {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<String>,
+    pub content: String,
+}
+
+/// The feed an item came from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Origin {
+    pub stream_id: String,
+    pub title: String,
+    #[serde(default)]
+    pub html_url: String,
+}
+
+/// A highlight, with an optional note.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: u64,
+    pub start: u64,
+    pub end: u64,
+    pub added_on: u64,
+    pub text: String,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default)]
+    pub user_id: u64,
+    #[serde(default)]
+    pub user_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_from_str() {
+        assert_eq!("read".parse::<Scope>(), Ok(Scope::Read));
+        assert_eq!("write".parse::<Scope>(), Ok(Scope::ReadWrite));
+        assert_eq!("read write".parse::<Scope>(), Ok(Scope::ReadWrite));
+        assert_eq!("read,write".parse::<Scope>(), Ok(Scope::ReadWrite));
+        assert!("admin".parse::<Scope>().is_err());
+        assert!("".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn test_stream_id_from_str() {
+        assert_eq!("starred".parse::<StreamId>(), Ok(StreamId::Starred));
+        assert_eq!(
+            "user/-/state/com.google/reading-list".parse::<StreamId>(),
+            Ok(StreamId::ReadingList)
+        );
+        assert_eq!(
+            "user/1005921515/state/com.google/read".parse::<StreamId>(),
+            Ok(StreamId::Read)
+        );
+        assert_eq!(
+            "user/-/label/Tech News".parse::<StreamId>(),
+            Ok(StreamId::Label("Tech News".to_string()))
+        );
+        assert_eq!(
+            "feed/http://example.com/rss".parse::<StreamId>(),
+            Ok(StreamId::Feed("http://example.com/rss".to_string()))
+        );
+        assert!("user/-/state/com.google/unknown"
+            .parse::<StreamId>()
+            .is_err());
+        assert!("favorites".parse::<StreamId>().is_err());
+    }
+
+    #[test]
+    fn test_stream_id_encoded() {
+        assert_eq!(
+            StreamId::Starred.encoded(),
+            "user%2F-%2Fstate%2Fcom.google%2Fstarred"
+        );
+        assert_eq!(
+            StreamId::Label("Tech & Science".to_string()).encoded(),
+            "user%2F-%2Flabel%2FTech%20%26%20Science"
+        );
+        assert_eq!(
+            StreamId::Feed("http://example.com/rss?a=1".to_string()).encoded(),
+            "feed%2Fhttp%3A%2F%2Fexample.com%2Frss%3Fa%3D1"
+        );
+    }
+
+    #[test]
+    fn test_item_accessors() {
+        let item: Item = serde_json::from_str(
+            r#"{
+                "id": "tag:google.com,2005:reader/item/0000000693c3bc0c",
+                "title": "Title",
+                "crawlTimeMsec": "1618211779000",
+                "timestampUsec": "1618211779000000",
+                "published": 1617969599,
+                "categories": [
+                    "user/1005921515/state/com.google/reading-list",
+                    "user/1005921515/state/com.google/read",
+                    "user/1005921515/label/Tech"
+                ],
+                "canonical": [{ "href": "https://example.com/a" }],
+                "enclosure": [{ "href": "https://example.com/a.mp3", "type": "audio/mpeg", "length": "1234" }],
+                "origin": { "streamId": "feed/https://example.com/rss", "title": "Example", "htmlUrl": "https://example.com/" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(item.url(), Some("https://example.com/a"));
+        assert_eq!(item.labels(), vec!["Tech"]);
+        assert_eq!(item.feed_title(), Some("Example"));
+        assert_eq!(item.crawl_time_msec, 1_618_211_779_000);
+        assert_eq!(item.enclosure[0].length, 1234);
+
+        let round_trip: Item =
+            serde_json::from_str(&serde_json::to_string(&item).unwrap()).unwrap();
+        assert_eq!(round_trip, item);
+    }
+
+    #[test]
+    fn test_scope_includes() {
+        assert!(Scope::ReadWrite.includes(Scope::Read));
+        assert!(Scope::ReadWrite.includes(Scope::ReadWrite));
+        assert!(Scope::Read.includes(Scope::Read));
+        assert!(!Scope::Read.includes(Scope::ReadWrite));
+    }
+
+    #[test]
+    fn test_unread_counts() {
+        let counts: UnreadCounts = serde_json::from_str(
+            r#"{"max":"1000","unreadcounts":[
+                {"id":"user/1005921515/state/com.google/reading-list","count":1000,"newestItemTimestampUsec":"1618211779000000"},
+                {"id":"feed/http://example.com/rss","count":"12","newestItemTimestampUsec":"1618211779000000"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let reading_list = counts.of(&StreamId::ReadingList);
+        assert_eq!(counts.describe(reading_list), "1000+");
+        let feed = counts.of(&StreamId::Feed("http://example.com/rss".to_string()));
+        assert_eq!(counts.describe(feed), "12");
+        assert_eq!(counts.of(&StreamId::Label("Tech".to_string())), 0);
+    }
 }
@@ -0,0 +1,231 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+use crate::infrastructure::ApiQuota;
+use crate::repositories::config_repository::ConfigError;
+use crate::services::authentication_service::AuthenticationError;
+
+// エラーメッセージに含めるレスポンス本文の最大文字数
+const MAX_BODY_CHARS: usize = 200;
+
+/// Errors of the API client, the auth and token services and the repositories.
+#[derive(Debug)]
+pub enum Error {
+    /// Authorization failed, or the saved token is missing or lacks a scope
+    Auth(AuthenticationError),
+    /// The refresh token was rejected; the profile has to be authorized again
+    TokenExpired,
+    /// HTTP 429
+    RateLimited {
+        reset_after: Option<Duration>,
+    },
+    /// Any other unsuccessful response, with its body
+    Http(StatusCode, String),
+    /// The request could not be sent or the response could not be read
+    Network(reqwest::Error),
+    Parse(serde_json::Error),
+    Config(ConfigError),
+    Io(io::Error),
+    /// The command cannot be carried out as given, such as `setup` on a profile with tokens
+    Usage(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Exit code of the process when a command fails with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Auth(_) => 3,
+            Error::TokenExpired => 4,
+            Error::RateLimited { .. } => 5,
+            Error::Http(..) => 6,
+            Error::Network(_) => 7,
+            Error::Parse(_) => 8,
+            Error::Config(_) => 9,
+            Error::Io(_) => 10,
+            Error::Usage(_) => 1,
+        }
+    }
+
+    /// Turns an unsuccessful response into `RateLimited` or `Http`.
+    pub async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Error::RateLimited {
+                reset_after: Self::rate_limit_reset_after(response.headers()),
+            };
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        Error::Http(status, body)
+    }
+
+    /// When a 429 may be retried: `X-Reader-Limits-Reset-After`, else `Retry-After` (seconds).
+    pub fn rate_limit_reset_after(headers: &HeaderMap) -> Option<Duration> {
+        ApiQuota::from_headers(headers)
+            .and_then(|quota| quota.reset_after)
+            .or_else(|| {
+                headers
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok())
+                    .map(Duration::from_secs)
+            })
+    }
+}
+
+/// Formats a wait such as the time until the quota resets: `5h 03m`, `12m 30s` or `45s`,
+/// followed by the local time it ends.
+pub fn describe_wait(wait: Duration) -> String {
+    let secs = wait.as_secs();
+    let relative = match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    };
+    let at = chrono::Local::now() + chrono::Duration::seconds(secs as i64);
+    format!("{} (at {})", relative, at.format("%Y-%m-%d %H:%M %:z"))
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Auth(e) => write!(f, "{}", e),
+            Error::TokenExpired => write!(
+                f,
+                "the refresh token was rejected; run 'reauth' to authorize again"
+            ),
+            Error::RateLimited { reset_after } => {
+                write!(f, "the Inoreader API request quota is used up")?;
+                match reset_after {
+                    Some(after) => write!(f, "; it resets in {}", describe_wait(*after)),
+                    None => Ok(()),
+                }
+            }
+            Error::Http(status, body) => {
+                write!(f, "the server returned {}", status)?;
+                let body = body.trim();
+                if body.is_empty() {
+                    return Ok(());
+                }
+                match body.char_indices().nth(MAX_BODY_CHARS) {
+                    Some((end, _)) => write!(f, ": {}...", &body[..end]),
+                    None => write!(f, ": {}", body),
+                }
+            }
+            Error::Network(e) => write!(f, "network error: {}", e),
+            Error::Parse(e) => write!(f, "failed to parse the response: {}", e),
+            Error::Config(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Auth(e) => Some(e),
+            Error::Network(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::TokenExpired | Error::RateLimited { .. } | Error::Http(..) | Error::Usage(_) => {
+                None
+            }
+        }
+    }
+}
+
+impl From<AuthenticationError> for Error {
+    fn from(e: AuthenticationError) -> Self {
+        match e {
+            AuthenticationError::Io(e) => Error::Io(e),
+            e => Error::Auth(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Network(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        // 資格情報ストアは読めなかった設定を io::Error に包んで返す
+        match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<ConfigError>())
+        {
+            Some(config_error) => Error::Config(config_error.clone()),
+            None => Error::Io(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            Error::Auth(AuthenticationError::MissingCode),
+            Error::TokenExpired,
+            Error::RateLimited { reset_after: None },
+            Error::Http(StatusCode::FORBIDDEN, String::new()),
+            Error::Network(reqwest::Client::new().get("not a url").build().unwrap_err()),
+            Error::Parse(serde_json::from_str::<u32>("x").unwrap_err()),
+            Error::Config(ConfigError::Syntax("x".to_string())),
+            Error::Io(io::Error::other("x")),
+            Error::Usage("x".to_string()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(Error::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        // 2 は clap の引数エラー
+        assert!(!codes.contains(&2));
+    }
+
+    #[test]
+    fn test_config_error_survives_io_error() {
+        let e = io::Error::from(ConfigError::Syntax("oops".to_string()));
+        assert!(matches!(
+            Error::from(e),
+            Error::Config(ConfigError::Syntax(_))
+        ));
+        assert!(matches!(
+            Error::from(io::Error::other("disk full")),
+            Error::Io(_)
+        ));
+    }
+
+    #[test]
+    fn test_http_error_truncates_body() {
+        let e = Error::Http(StatusCode::FORBIDDEN, "x".repeat(500));
+        let message = e.to_string();
+        assert!(message.starts_with("the server returned 403 Forbidden: xxx"));
+        assert!(message.ends_with("..."));
+        assert!(message.len() < 300);
+    }
+}
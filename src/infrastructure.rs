@@ -1,77 +1,1385 @@
-use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
-use reqwest::Client;
+use std::borrow::Cow;
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, StatusCode};
 use services::response_parser_service::ResponseParser;
+use services::token_service::TokenService;
+use tokio::sync::Mutex;
+use url::Url;
 
+use crate::domain::{ApiResponse, Item, ItemWarning, StreamId, UnreadCounts, UserInfo};
+use crate::error::Error;
+use crate::repositories::config_repository::ConfigRepository;
 use crate::services;
 
-const ENDPOINT_URL: &str = "https://www.inoreader.com/reader/api/0";
-const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
+pub const DEFAULT_BASE_URL: &str = "https://www.inoreader.com";
+const API_PATH: &str = "/reader/api/0";
+// 有効期限のこの秒数前からリフレッシュする
+const TOKEN_REFRESH_MARGIN_SECS: u64 = 60;
+const DEFAULT_PAGE_SIZE: u32 = 100;
+// edit-tag 1 回で送る記事 ID の数
+pub const EDIT_TAG_BATCH_SIZE: usize = 100;
+// 429 のときクォータのリセットまでこの時間以内なら待ってから再送する
+const DEFAULT_MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// 9999-12-31T23:59:59Z
+const MAX_TIME: i64 = 253_402_300_799;
+
+const ZONE1_USAGE_HEADER: &str = "X-Reader-Zone1-Usage";
+const ZONE1_LIMIT_HEADER: &str = "X-Reader-Zone1-Limit";
+const ZONE2_USAGE_HEADER: &str = "X-Reader-Zone2-Usage";
+const ZONE2_LIMIT_HEADER: &str = "X-Reader-Zone2-Limit";
+const LIMITS_RESET_AFTER_HEADER: &str = "X-Reader-Limits-Reset-After";
+
+/// Base URL of Inoreader: `INOREADER_BASE_URL` if set (e.g. a local mock server), else
+/// `DEFAULT_BASE_URL`. The API and OAuth endpoints are paths below it.
+pub fn base_url_from_env() -> String {
+    env::var("INOREADER_BASE_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+}
+
+/// How failed GET requests are retried: exponential backoff with full jitter, on connection
+/// errors, timeouts and 408/500/502/503/504. Other requests are never retried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one. 1 disables retries.
+    pub max_attempts: u32,
+    /// Upper bound of the first delay, doubled on every retry
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (0 for the first retry): a random duration up to
+    /// `base_delay * 2^retry`, capped at `max_delay`.
+    fn delay(&self, retry: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        cap.mul_f64(rand::random::<f64>())
+    }
+
+    fn is_retryable_status(status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::REQUEST_TIMEOUT
+                | StatusCode::INTERNAL_SERVER_ERROR
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        )
+    }
+
+    fn is_retryable_error(e: &reqwest::Error) -> bool {
+        e.is_timeout() || e.is_connect()
+    }
+}
+
+/// Requests made today and the daily limit of one API zone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ZoneUsage {
+    pub usage: u64,
+    pub limit: u64,
+}
+
+/// Daily request quota reported in the `X-Reader-*` response headers. Zone 1 counts reads
+/// such as `stream/contents`, Zone 2 counts writes such as `edit-tag`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ApiQuota {
+    pub zone1: Option<ZoneUsage>,
+    pub zone2: Option<ZoneUsage>,
+    /// Time until the quotas reset, as of the response
+    pub reset_after: Option<Duration>,
+}
+
+impl ApiQuota {
+    /// Reads the quota headers. `None` if the response has none of them.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let number = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<f64>().ok())
+                .filter(|value| value.is_finite() && *value >= 0.0)
+        };
+        let zone = |usage: &str, limit: &str| match (number(usage), number(limit)) {
+            (Some(usage), Some(limit)) => Some(ZoneUsage {
+                usage: usage as u64,
+                limit: limit as u64,
+            }),
+            _ => None,
+        };
+
+        let quota = ApiQuota {
+            zone1: zone(ZONE1_USAGE_HEADER, ZONE1_LIMIT_HEADER),
+            zone2: zone(ZONE2_USAGE_HEADER, ZONE2_LIMIT_HEADER),
+            reset_after: number(LIMITS_RESET_AFTER_HEADER).map(Duration::from_secs_f64),
+        };
+        (quota != ApiQuota::default()).then_some(quota)
+    }
+}
+
+/// Where a `fetch_stream_contents` run stops besides the end of the stream. `None` is unlimited.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FetchLimits {
+    pub max_items: Option<usize>,
+    pub max_pages: Option<usize>,
+}
+
+/// Why a run stopped before the end of the stream.
+#[derive(Debug)]
+pub enum StopReason {
+    ItemLimit,
+    PageLimit,
+    Failed(Error),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::ItemLimit => write!(f, "reached --limit"),
+            StopReason::PageLimit => write!(f, "reached --max-pages"),
+            StopReason::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Position of `stream_pages` between two requests.
+struct PageCursor {
+    continuation: Option<String>,
+    items: usize,
+    pages: usize,
+}
+
+/// Result of a `fetch_stream_contents` run.
+pub struct FetchedStream {
+    pub articles: Vec<Item>,
+    pub pages: usize,
+    /// Where to resume when the run stopped before the end of the stream
+    pub continuation: Option<String>,
+    pub stopped: Option<StopReason>,
+    /// Items that were skipped or saved with missing data
+    pub warnings: Vec<ItemWarning>,
+}
+
+/// Query parameters of `stream/contents`, built up with the `with_*` methods.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamQuery {
+    count: u32,
+    since: Option<i64>,
+    until: Option<i64>,
+    oldest_first: bool,
+    exclude: Option<StreamId>,
+    include: Option<StreamId>,
+    annotations: bool,
+}
+
+impl Default for StreamQuery {
+    fn default() -> Self {
+        StreamQuery {
+            count: DEFAULT_PAGE_SIZE,
+            since: None,
+            until: None,
+            oldest_first: false,
+            exclude: None,
+            include: None,
+            annotations: false,
+        }
+    }
+}
+
+impl StreamQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Items per page (`n`).
+    pub fn with_count(mut self, count: u32) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// Only items newer than this Unix time (`ot`).
+    pub fn with_since(mut self, timestamp: Option<i64>) -> Self {
+        self.since = timestamp;
+        self
+    }
+
+    /// Only items older than this Unix time (`nt`).
+    pub fn with_until(mut self, timestamp: Option<i64>) -> Self {
+        self.until = timestamp;
+        self
+    }
+
+    /// Oldest items first (`r=o`).
+    pub fn with_oldest_first(mut self, oldest_first: bool) -> Self {
+        self.oldest_first = oldest_first;
+        self
+    }
+
+    /// Leave out items in this stream (`xt`), e.g. `StreamId::Read` for unread items only.
+    pub fn with_exclude(mut self, stream: Option<StreamId>) -> Self {
+        self.exclude = stream;
+        self
+    }
+
+    /// Only items that are also in this stream (`it`).
+    pub fn with_include(mut self, stream: Option<StreamId>) -> Self {
+        self.include = stream;
+        self
+    }
+
+    /// Include the highlights and notes of each item (`annotations=1`).
+    pub fn with_annotations(mut self, annotations: bool) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Identifies the stream and query a saved continuation belongs to.
+    pub fn fingerprint(&self, stream: &StreamId) -> String {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(self.query_pairs(None))
+            .finish();
+        format!("{}?{}", stream.as_id(), query)
+    }
+
+    fn query_pairs(&self, continuation: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![("n", self.count.to_string())];
+        if let Some(since) = self.since {
+            pairs.push(("ot", since.to_string()));
+        }
+        if let Some(until) = self.until {
+            pairs.push(("nt", until.to_string()));
+        }
+        if self.oldest_first {
+            pairs.push(("r", "o".to_string()));
+        }
+        if let Some(exclude) = &self.exclude {
+            pairs.push(("xt", exclude.as_id()));
+        }
+        if let Some(include) = &self.include {
+            pairs.push(("it", include.as_id()));
+        }
+        if self.annotations {
+            pairs.push(("annotations", "1".to_string()));
+        }
+        if let Some(continuation) = continuation {
+            pairs.push(("c", continuation.to_string()));
+        }
+        pairs
+    }
+
+    /// Parses `--since`/`--until`: a Unix time, RFC 3339, a date (midnight, local time), or
+    /// an age such as `30d`, `12h` or `2w` meaning that long before now. Times before 1970 or
+    /// after 9999 are rejected, so that they also fit in microseconds.
+    pub fn parse_time(s: &str) -> Result<i64, String> {
+        let time = Self::parse_any_time(s)?;
+        match (0..=MAX_TIME).contains(&time) {
+            true => Ok(time),
+            false => Err(format!("'{}' is not between 1970 and 9999", s)),
+        }
+    }
+
+    fn parse_any_time(s: &str) -> Result<i64, String> {
+        if let Ok(timestamp) = s.parse::<i64>() {
+            return Ok(timestamp);
+        }
+        if let Some(age) = Self::parse_age(s) {
+            return Ok(Local::now().timestamp() - age);
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(time.timestamp());
+        }
+        let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| {
+            format!(
+                "invalid time '{}' (expected YYYY-MM-DD, RFC 3339, a Unix time or an age like 30d)",
+                s
+            )
+        })?;
+        Local
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+            .earliest()
+            .map(|time| time.timestamp())
+            .ok_or_else(|| format!("'{}' does not exist in the local time zone", s))
+    }
+
+    /// Seconds in an age like `90d`: a number followed by s, m, h, d or w.
+    pub fn parse_age(s: &str) -> Option<i64> {
+        let unit = match s.chars().last()? {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let number: i64 = s[..s.len() - 1].parse().ok().filter(|n| *n >= 0)?;
+        number.checked_mul(unit)
+    }
+}
+
+/// Tags to add to and remove from items with `edit-tag`, e.g. removing the star.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagChange {
+    pub add: Option<StreamId>,
+    pub remove: Option<StreamId>,
+}
+
+impl TagChange {
+    pub fn add(tag: StreamId) -> Self {
+        TagChange {
+            add: Some(tag),
+            remove: None,
+        }
+    }
+
+    pub fn remove(tag: StreamId) -> Self {
+        TagChange {
+            add: None,
+            remove: Some(tag),
+        }
+    }
+
+    fn form_pairs<'a>(&self, item_ids: &'a [String]) -> Vec<(&'static str, Cow<'a, str>)> {
+        let mut pairs: Vec<_> = item_ids
+            .iter()
+            .map(|id| ("i", Cow::Borrowed(id.as_str())))
+            .collect();
+        if let Some(add) = &self.add {
+            pairs.push(("a", Cow::Owned(add.as_id())));
+        }
+        if let Some(remove) = &self.remove {
+            pairs.push(("r", Cow::Owned(remove.as_id())));
+        }
+        pairs
+    }
+}
 
 pub struct InoreaderClient {
     http_client: Client,
+    base_url: String,
     client_id: String,
     client_secret: String,
+    config_path: String,
+    profile: String,
+    tokens: Mutex<Tokens>,
+    max_rate_limit_wait: Duration,
+    retry_policy: RetryPolicy,
+    edit_tag_batch_size: usize,
+    quota: std::sync::Mutex<Option<ApiQuota>>,
+}
+
+struct Tokens {
     access_token: String,
+    refresh_token: String,
+    expires_in: u64,
+}
+
+impl Tokens {
+    fn expires_soon(&self, now: u64) -> bool {
+        now + TOKEN_REFRESH_MARGIN_SECS >= self.expires_in
+    }
 }
 
 impl InoreaderClient {
-    pub fn new(client_id: String, client_secret: String, access_token: String) -> Self {
+    pub fn new(
+        client_id: String,
+        client_secret: String,
+        config_repository: &ConfigRepository,
+    ) -> Self {
         InoreaderClient {
-            http_client: Client::new(),
+            http_client: Self::http_client(DEFAULT_CONNECT_TIMEOUT, DEFAULT_REQUEST_TIMEOUT),
+            base_url: base_url_from_env(),
             client_id,
             client_secret,
-            access_token,
-        }
-    }
-
-    pub async fn fetch_stream_contents(&self) -> Result<Vec<(String, String)>, reqwest::Error> {
-        let input: &str = "user/-/state/com.google/starred";
-        let encoded: String = utf8_percent_encode(input, FRAGMENT).to_string();
-        let mut continuation: Option<String> = Some(String::new());
-        let mut counter: i32 = 0;
-        let mut all_articles: Vec<(String, String)> = Vec::new();
-        let max_iterations: i32 = 10;
-
-        while counter < max_iterations && continuation.is_some() {
-            let url: String = format!(
-                "{}/stream/contents/{}?AppId={}&AppKey={}&n=100&c={}",
-                ENDPOINT_URL,
-                encoded,
-                &self.client_id,
-                &self.client_secret,
-                continuation.as_ref().unwrap()
-            );
+            config_path: config_repository.get_config_path().to_string(),
+            profile: config_repository.get_profile().name.clone(),
+            tokens: Mutex::new(Tokens {
+                access_token: config_repository.get_saved_access_token(),
+                refresh_token: config_repository.get_saved_refresh_token(),
+                expires_in: config_repository.get_saved_expires_in(),
+            }),
+            max_rate_limit_wait: DEFAULT_MAX_RATE_LIMIT_WAIT,
+            retry_policy: RetryPolicy::default(),
+            edit_tag_batch_size: EDIT_TAG_BATCH_SIZE,
+            quota: std::sync::Mutex::new(None),
+        }
+    }
 
-            let response: reqwest::Response = self
+    fn http_client(connect_timeout: Duration, request_timeout: Duration) -> Client {
+        Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(request_timeout)
+            .build()
+            .expect("Failed to create the HTTP client")
+    }
+
+    /// Sends the requests to `base_url` instead of Inoreader, e.g. a local mock server.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Time limit of a single attempt, from connecting to reading the whole body.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.http_client = Self::http_client(DEFAULT_CONNECT_TIMEOUT, request_timeout);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// On a 429, wait for the quota to reset if that is at most `max_wait` away and send the
+    /// request again. Otherwise the request fails with `Error::RateLimited`.
+    pub fn with_max_rate_limit_wait(mut self, max_wait: Duration) -> Self {
+        self.max_rate_limit_wait = max_wait;
+        self
+    }
+
+    /// Items per `edit-tag` request; `EDIT_TAG_BATCH_SIZE` by default.
+    pub fn with_edit_tag_batch_size(mut self, batch_size: usize) -> Self {
+        self.edit_tag_batch_size = batch_size.max(1);
+        self
+    }
+
+    /// The quota reported by the last response that had the usage headers.
+    pub fn quota(&self) -> Option<ApiQuota> {
+        *self.quota.lock().unwrap()
+    }
+
+    /// Returns an access token that is not about to expire, refreshing it first if needed.
+    async fn valid_access_token(&self) -> Result<String, Error> {
+        let mut tokens = self.tokens.lock().await;
+        if tokens.expires_soon(Self::now()) {
+            self.refresh(&mut tokens).await?;
+        }
+        Ok(tokens.access_token.clone())
+    }
+
+    /// Refreshes after a 401, unless another request already replaced `rejected_token`.
+    async fn refresh_rejected_token(&self, rejected_token: &str) -> Result<String, Error> {
+        let mut tokens = self.tokens.lock().await;
+        if tokens.access_token == rejected_token {
+            self.refresh(&mut tokens).await?;
+        }
+        Ok(tokens.access_token.clone())
+    }
+
+    async fn refresh(&self, tokens: &mut Tokens) -> Result<(), Error> {
+        let config = TokenService::new(
+            &self.client_id,
+            &self.client_secret,
+            &tokens.refresh_token,
+            &self.config_path,
+            &self.profile,
+        )
+        .with_base_url(&self.base_url)
+        .refreshing_token()
+        .await?;
+
+        tokens.access_token = config.access_token;
+        tokens.refresh_token = config.refresh_token;
+        tokens.expires_in = config.expires_in;
+        Ok(())
+    }
+
+    /// Sends an authorized GET, see `authorized`.
+    async fn get(&self, url: &str) -> Result<reqwest::Response, Error> {
+        self.authorized(|access_token| async move { self.send_get(url, &access_token).await })
+            .await
+    }
+
+    /// Sends an authorized POST of `form`, see `authorized`. Not retried on transient failures.
+    async fn post_form(
+        &self,
+        url: &str,
+        form: &[(&str, Cow<'_, str>)],
+    ) -> Result<reqwest::Response, Error> {
+        self.authorized(|access_token| async move {
+            let result = self
                 .http_client
-                .get(&url)
-                .header("Authorization", format!("Bearer {}", &self.access_token))
+                .post(url)
+                .header("Authorization", format!("Bearer {}", access_token))
+                .form(form)
                 .send()
-                .await?;
+                .await;
+            if let Ok(response) = &result {
+                self.record_quota(response);
+            }
+            result
+        })
+        .await
+    }
 
-            if response.status().is_success() {
-                let content: String = response.text().await?;
-                match ResponseParser::parse_response(&content) {
-                    Ok((articles, new_continuation)) => {
-                        all_articles.extend(articles.iter().cloned());
-                        continuation = new_continuation;
-                    }
-                    Err(e) => {
-                        eprintln!("Error parsing response: {}", e);
-                        break;
-                    }
-                }
-            } else {
+    /// Sends a request with `send(access_token)`. On a 401 the token is refreshed and the
+    /// request sent again once, and on a 429 it is sent again once the quota resets if that
+    /// is soon enough. A 401 with the refreshed token is `Error::TokenExpired`; other
+    /// unsuccessful responses are returned as `Error::Http` or `Error::RateLimited`.
+    async fn authorized<F, Fut>(&self, send: F) -> Result<reqwest::Response, Error>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<reqwest::Response, reqwest::Error>>,
+    {
+        let mut access_token = self.valid_access_token().await?;
+        let mut response = send(access_token.clone()).await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            access_token = self.refresh_rejected_token(&access_token).await?;
+            response = send(access_token.clone()).await?;
+            if response.status() == StatusCode::UNAUTHORIZED {
+                return Err(Error::TokenExpired);
+            }
+        }
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            if let Some(wait) = Error::rate_limit_reset_after(response.headers())
+                .filter(|wait| *wait <= self.max_rate_limit_wait)
+            {
                 eprintln!(
-                    "Failed to fetch subscriptions. Status: {}",
-                    response.status()
+                    "Rate limited by the Inoreader API; retrying in {} s.",
+                    wait.as_secs()
                 );
-                break;
+                tokio::time::sleep(wait).await;
+                response = send(access_token).await?;
             }
-            counter += 1;
-            println!("Fetching page {}...", counter)
         }
 
-        Ok(all_articles)
+        if !response.status().is_success() {
+            return Err(Error::from_response(response).await);
+        }
+        Ok(response)
+    }
+
+    /// Sends a GET, retrying transient failures according to the retry policy.
+    async fn send_get(
+        &self,
+        url: &str,
+        access_token: &str,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let mut retry = 0;
+        loop {
+            let result = self
+                .http_client
+                .get(url)
+                .header("Authorization", format!("Bearer {}", access_token))
+                .send()
+                .await;
+            if let Ok(response) = &result {
+                self.record_quota(response);
+            }
+
+            let failure = match &result {
+                Ok(response) if RetryPolicy::is_retryable_status(response.status()) => {
+                    response.status().to_string()
+                }
+                Err(e) if RetryPolicy::is_retryable_error(e) => e.to_string(),
+                _ => return result,
+            };
+            if retry + 1 >= self.retry_policy.max_attempts {
+                return result;
+            }
+
+            let delay = self.retry_policy.delay(retry);
+            retry += 1;
+            eprintln!(
+                "Request failed ({}); retrying in {} ms (attempt {} of {}).",
+                failure,
+                delay.as_millis(),
+                retry + 1,
+                self.retry_policy.max_attempts
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn record_quota(&self, response: &reqwest::Response) {
+        if let Some(quota) = ApiQuota::from_headers(response.headers()) {
+            *self.quota.lock().unwrap() = Some(quota);
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+    }
+
+    /// Returns the account the access token belongs to.
+    pub async fn user_info(&self) -> Result<UserInfo, Error> {
+        let url = format!("{}{}/user-info", self.base_url, API_PATH);
+        let content = self.get(&url).await?.text().await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Unread item counts of all streams. They are capped at `UnreadCounts::max`.
+    pub async fn unread_count(&self) -> Result<UnreadCounts, Error> {
+        let url = format!("{}{}/unread-count", self.base_url, API_PATH);
+        let content = self.get(&url).await?.text().await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Marks every item of `stream` older than `older_than` (Unix seconds) as read, in a
+    /// single request.
+    pub async fn mark_all_as_read(&self, stream: &StreamId, older_than: i64) -> Result<(), Error> {
+        let url = format!("{}{}/mark-all-as-read", self.base_url, API_PATH);
+        // ts はマイクロ秒
+        let ts = older_than.checked_mul(1_000_000).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is out of range for mark-all-as-read", older_than),
+            )
+        })?;
+        let form = [
+            ("s", Cow::Owned(stream.as_id())),
+            ("ts", Cow::Owned(ts.to_string())),
+        ];
+        self.post_form(&url, &form).await?;
+        Ok(())
+    }
+
+    fn stream_contents_url(
+        &self,
+        stream: &StreamId,
+        query: &StreamQuery,
+        continuation: Option<&str>,
+    ) -> String {
+        let mut url = Url::parse(&format!(
+            "{}{}/stream/contents/{}",
+            self.base_url,
+            API_PATH,
+            stream.encoded()
+        ))
+        .expect("stream contents URL is valid");
+        url.query_pairs_mut()
+            .append_pair("AppId", &self.client_id)
+            .append_pair("AppKey", &self.client_secret)
+            .extend_pairs(query.query_pairs(continuation));
+        url.to_string()
+    }
+
+    /// Follows the continuation from `start` (or the top of the stream) until the end of the
+    /// stream, one of `limits`, or an error. Errors end the run but keep the pages read so far.
+    pub async fn fetch_stream_contents(
+        &self,
+        stream: &StreamId,
+        query: &StreamQuery,
+        limits: FetchLimits,
+        start: Option<String>,
+    ) -> FetchedStream {
+        let mut fetched = FetchedStream {
+            articles: Vec::new(),
+            pages: 0,
+            continuation: start.clone(),
+            stopped: None,
+            warnings: Vec::new(),
+        };
+
+        let mut pages = pin!(self.stream_pages(stream, query, limits, start));
+        while let Some(page) = pages.next().await {
+            match page {
+                Ok(page) => {
+                    fetched.pages += 1;
+                    println!(
+                        "Fetched page {} ({} items)",
+                        fetched.pages,
+                        page.items.len()
+                    );
+                    fetched.articles.extend(page.items);
+                    fetched.warnings.extend(page.warnings);
+                    fetched.continuation = page.continuation;
+                }
+                Err(e) => {
+                    fetched.stopped = Some(StopReason::Failed(e));
+                    break;
+                }
+            }
+        }
+
+        if fetched.stopped.is_none() && fetched.continuation.is_some() {
+            fetched.stopped = match limits.max_pages {
+                Some(max) if fetched.pages >= max => Some(StopReason::PageLimit),
+                _ => Some(StopReason::ItemLimit),
+            };
+        }
+        fetched
+    }
+
+    /// Items of `stream`, read lazily: the next page is requested only when the items of
+    /// the previous one have been consumed. The stream ends after the first error.
+    pub fn stream_items<'a>(
+        &'a self,
+        stream: &'a StreamId,
+        query: &'a StreamQuery,
+        start: Option<String>,
+    ) -> impl Stream<Item = Result<Item, Error>> + 'a {
+        self.stream_pages(stream, query, FetchLimits::default(), start)
+            .map_ok(|page| stream::iter(page.items.into_iter().map(Ok)))
+            .try_flatten()
+    }
+
+    /// Pages of `stream` from `start` (or the top), following the continuation until the end
+    /// of the stream or one of `limits`.
+    pub fn stream_pages<'a>(
+        &'a self,
+        stream: &'a StreamId,
+        query: &'a StreamQuery,
+        limits: FetchLimits,
+        start: Option<String>,
+    ) -> impl Stream<Item = Result<ApiResponse, Error>> + 'a {
+        let cursor = PageCursor {
+            continuation: start,
+            items: 0,
+            pages: 0,
+        };
+
+        // 状態が None になったら終了
+        stream::try_unfold(Some(cursor), move |cursor| async move {
+            let mut cursor = match cursor {
+                Some(cursor) => cursor,
+                None => return Ok(None),
+            };
+            if limits.max_pages.is_some_and(|max| cursor.pages >= max) {
+                return Ok(None);
+            }
+            // 最後のページは残り件数だけ要求し、続きから再開しても取りこぼさないようにする
+            let remaining = limits.max_items.map(|max| max.saturating_sub(cursor.items));
+            let page_query = match remaining {
+                Some(0) => return Ok(None),
+                Some(remaining) if remaining < query.count as usize => {
+                    Cow::Owned(query.clone().with_count(remaining as u32))
+                }
+                _ => Cow::Borrowed(query),
+            };
+
+            let page = self
+                .fetch_page(stream, &page_query, cursor.continuation.as_deref())
+                .await?;
+            cursor.items += page.items.len();
+            cursor.pages += 1;
+            cursor.continuation = page.continuation.clone();
+            let next = cursor.continuation.is_some().then_some(cursor);
+            Ok(Some((page, next)))
+        })
+        .fuse()
+    }
+
+    /// Applies `change` to the items, one `edit-tag` request per batch of item IDs. Yields each
+    /// batch once it is done; the stream ends after the first error, leaving the remaining
+    /// batches untouched.
+    pub fn edit_tag<'a>(
+        &'a self,
+        item_ids: &'a [String],
+        change: &'a TagChange,
+    ) -> impl Stream<Item = Result<&'a [String], Error>> + 'a {
+        let url = format!("{}{}/edit-tag", self.base_url, API_PATH);
+        let batches = item_ids.chunks(self.edit_tag_batch_size);
+
+        // 状態が None になったら終了
+        stream::unfold(Some(batches), move |batches| {
+            let url = url.clone();
+            async move {
+                let mut batches = batches?;
+                let batch = batches.next()?;
+                match self.post_form(&url, &change.form_pairs(batch)).await {
+                    Ok(_) => Some((Ok(batch), Some(batches))),
+                    Err(e) => Some((Err(e), None)),
+                }
+            }
+        })
+    }
+
+    async fn fetch_page(
+        &self,
+        stream: &StreamId,
+        query: &StreamQuery,
+        continuation: Option<&str>,
+    ) -> Result<ApiResponse, Error> {
+        let url: String = self.stream_contents_url(stream, query, continuation);
+        let response: reqwest::Response = self.get(&url).await?;
+        let content: String = response.text().await?;
+        Ok(ResponseParser::parse_page(&content)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Config;
+    use wiremock::matchers::{
+        body_string, body_string_contains, method, path, path_regex, query_param,
+        query_param_is_missing,
+    };
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn page(titles: &[&str], continuation: Option<&str>) -> serde_json::Value {
+        let items: Vec<_> = titles
+            .iter()
+            .map(|title| {
+                serde_json::json!({
+                    "id": format!("tag:google.com,2005:reader/item/{}", title),
+                    "title": title,
+                    "canonical": [{ "href": format!("https://example.com/{}", title) }]
+                })
+            })
+            .collect();
+        serde_json::json!({ "items": items, "continuation": continuation })
+    }
+
+    /// A client with an unexpired token, talking to `server`.
+    fn test_client(server: &MockServer, dir: &tempfile::TempDir) -> InoreaderClient {
+        let config_path = dir.path().join("config");
+        let config_path = config_path.to_str().unwrap();
+        ConfigRepository::new(config_path, "default")
+            .unwrap()
+            .save_config(&Config {
+                authorization_code: String::new(),
+                state: String::new(),
+                access_token: "access".to_string(),
+                refresh_token: "refresh".to_string(),
+                expires_in: InoreaderClient::now() + 3600,
+                scope: "read".to_string(),
+            })
+            .unwrap();
+        let repository = ConfigRepository::new(config_path, "default").unwrap();
+
+        InoreaderClient::new("id".to_string(), "secret".to_string(), &repository)
+            .with_base_url(&server.uri())
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(5),
+            })
+    }
+
+    async fn mount_pages(server: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path_regex("^/reader/api/0/stream/contents/"))
+            .and(query_param_is_missing("c"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page(&["a", "b"], Some("c1"))))
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("c", "c1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page(&["c"], None)))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_stream_items_follows_continuation_lazily() {
+        let server = MockServer::start().await;
+        mount_pages(&server).await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+        let query = StreamQuery::new();
+
+        let mut items = pin!(client.stream_items(&StreamId::Starred, &query, None));
+        assert_eq!(items.next().await.unwrap().unwrap().title, "a");
+        assert_eq!(items.next().await.unwrap().unwrap().title, "b");
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+
+        assert_eq!(items.next().await.unwrap().unwrap().title, "c");
+        assert!(items.next().await.is_none());
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stream_items_stops_after_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+        let query = StreamQuery::new();
+
+        let mut items = pin!(client.stream_items(&StreamId::Starred, &query, None));
+        assert!(matches!(
+            items.next().await,
+            Some(Err(Error::Http(StatusCode::SERVICE_UNAVAILABLE, _)))
+        ));
+        assert!(items.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_stream_contents_limits() {
+        let server = MockServer::start().await;
+        mount_pages(&server).await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+        let query = StreamQuery::new();
+
+        let all = client
+            .fetch_stream_contents(&StreamId::Starred, &query, FetchLimits::default(), None)
+            .await;
+        assert_eq!(all.articles.len(), 3);
+        assert!(all.stopped.is_none());
+        assert_eq!(all.continuation, None);
+
+        let limits = FetchLimits {
+            max_items: None,
+            max_pages: Some(1),
+        };
+        let first = client
+            .fetch_stream_contents(&StreamId::Starred, &query, limits, None)
+            .await;
+        assert_eq!(first.articles.len(), 2);
+        assert!(matches!(first.stopped, Some(StopReason::PageLimit)));
+        assert_eq!(first.continuation.as_deref(), Some("c1"));
+
+        let rest = client
+            .fetch_stream_contents(&StreamId::Starred, &query, limits, first.continuation)
+            .await;
+        assert_eq!(rest.articles[0].title, "c");
+        assert!(rest.stopped.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_stream_contents_keeps_pages_before_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param_is_missing("c"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page(&["a"], Some("c1"))))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("c", "c1"))
+            .respond_with(ResponseTemplate::new(403).set_body_string("Daily request limit reached"))
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+
+        let fetched = client
+            .fetch_stream_contents(
+                &StreamId::Starred,
+                &StreamQuery::new(),
+                FetchLimits::default(),
+                None,
+            )
+            .await;
+        assert_eq!(fetched.articles.len(), 1);
+        assert_eq!(fetched.continuation.as_deref(), Some("c1"));
+        match fetched.stopped {
+            Some(StopReason::Failed(Error::Http(status, body))) => {
+                assert_eq!(status, StatusCode::FORBIDDEN);
+                assert_eq!(body, "Daily request limit reached");
+            }
+            other => panic!("unexpected stop: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+        for _ in 0..100 {
+            assert!(policy.delay(0) <= Duration::from_millis(100));
+            assert!(policy.delay(1) <= Duration::from_millis(200));
+            assert!(policy.delay(5) <= Duration::from_millis(300));
+            assert!(policy.delay(40) <= Duration::from_millis(300));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_statuses() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        mount_pages(&server).await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+
+        let fetched = client
+            .fetch_stream_contents(
+                &StreamId::Starred,
+                &StreamQuery::new(),
+                FetchLimits::default(),
+                None,
+            )
+            .await;
+        assert!(fetched.stopped.is_none());
+        assert_eq!(fetched.articles.len(), 3);
+        assert_eq!(server.received_requests().await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+
+        assert!(matches!(
+            client.user_info().await,
+            Err(Error::Http(StatusCode::INTERNAL_SERVER_ERROR, _))
+        ));
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+
+        assert!(matches!(
+            client.user_info().await,
+            Err(Error::Http(StatusCode::NOT_FOUND, _))
+        ));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retries_timeouts() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "userId": "1", "userName": "Jane" })),
+            )
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir).with_request_timeout(Duration::from_millis(200));
+
+        assert_eq!(client.user_info().await.unwrap().user_name, "Jane");
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_api_quota_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(ApiQuota::from_headers(&headers), None);
+
+        headers.insert(ZONE1_USAGE_HEADER, "120".parse().unwrap());
+        headers.insert(ZONE1_LIMIT_HEADER, "5000".parse().unwrap());
+        headers.insert(ZONE2_USAGE_HEADER, "garbage".parse().unwrap());
+        headers.insert(ZONE2_LIMIT_HEADER, "1000".parse().unwrap());
+        headers.insert(LIMITS_RESET_AFTER_HEADER, "3600.5".parse().unwrap());
+        assert_eq!(
+            ApiQuota::from_headers(&headers),
+            Some(ApiQuota {
+                zone1: Some(ZoneUsage {
+                    usage: 120,
+                    limit: 5000
+                }),
+                zone2: None,
+                reset_after: Some(Duration::from_millis(3_600_500)),
+            })
+        );
+    }
+
+    fn rate_limited(reset_after: &str) -> ResponseTemplate {
+        ResponseTemplate::new(429)
+            .insert_header(ZONE1_USAGE_HEADER, "5000")
+            .insert_header(ZONE1_LIMIT_HEADER, "5000")
+            .insert_header(LIMITS_RESET_AFTER_HEADER, reset_after)
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_waits_for_reset() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(rate_limited("0"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(page(&["a"], None))
+                    .insert_header(ZONE1_USAGE_HEADER, "1")
+                    .insert_header(ZONE1_LIMIT_HEADER, "5000"),
+            )
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+
+        let fetched = client
+            .fetch_stream_contents(
+                &StreamId::Starred,
+                &StreamQuery::new(),
+                FetchLimits::default(),
+                None,
+            )
+            .await;
+        assert!(fetched.stopped.is_none());
+        assert_eq!(fetched.articles.len(), 1);
+        assert_eq!(
+            client.quota().and_then(|quota| quota.zone1),
+            Some(ZoneUsage {
+                usage: 1,
+                limit: 5000
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_aborts_when_reset_is_far() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(rate_limited("3600"))
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+
+        let error = client.user_info().await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::RateLimited {
+                reset_after: Some(wait)
+            } if wait == Duration::from_secs(3600)
+        ));
+        assert!(error.to_string().contains("resets in 1h 00m"));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+        assert_eq!(client.quota().unwrap().zone1.unwrap().usage, 5000);
+    }
+
+    fn item_ids(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|n| format!("tag:google.com,2005:reader/item/{}", n))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_edit_tag_sends_batches() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/reader/api/0/edit-tag"))
+            .and(body_string_contains(
+                "r=user%2F-%2Fstate%2Fcom.google%2Fstarred",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .expect(3)
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir).with_edit_tag_batch_size(2);
+        let ids = item_ids(5);
+        let change = TagChange::remove(StreamId::Starred);
+
+        let batches: Vec<_> = client.edit_tag(&ids, &change).try_collect().await.unwrap();
+
+        assert_eq!(batches, vec![&ids[0..2], &ids[2..4], &ids[4..5]]);
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&requests[2].body),
+            "i=tag%3Agoogle.com%2C2005%3Areader%2Fitem%2F4&r=user%2F-%2Fstate%2Fcom.google%2Fstarred"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit_tag_stops_after_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string(
+                "i=tag%3Agoogle.com%2C2005%3Areader%2Fitem%2F1&a=user%2F-%2Fstate%2Fcom.google%2Fread",
+            ))
+            .respond_with(ResponseTemplate::new(403).set_body_string("Forbidden"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir).with_edit_tag_batch_size(1);
+        let ids = item_ids(3);
+        let change = TagChange::add(StreamId::Read);
+
+        let results: Vec<_> = client.edit_tag(&ids, &change).collect().await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap(), &&ids[0..1]);
+        assert!(matches!(
+            results[1],
+            Err(Error::Http(StatusCode::FORBIDDEN, _))
+        ));
+        // 失敗した後のバッチは送らない
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_mark_all_as_read_sends_cutoff_in_microseconds() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/reader/api/0/mark-all-as-read"))
+            .and(body_string("s=user%2F-%2Flabel%2FTech&ts=1700000000000000"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = test_client(&server, &dir);
+
+        client
+            .mark_all_as_read(&StreamId::Label("Tech".to_string()), 1_700_000_000)
+            .await
+            .unwrap();
+        assert!(client
+            .mark_all_as_read(&StreamId::Label("Tech".to_string()), i64::MAX)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_user_info_deserialize() {
+        let user_info: UserInfo = serde_json::from_str(
+            r#"{"userId":"1001","userName":"Jane","userProfileId":"1001","userEmail":"jane@example.com","isBloggerUser":false}"#,
+        )
+        .unwrap();
+
+        assert_eq!(user_info.user_id, "1001");
+        assert_eq!(user_info.user_name, "Jane");
+        assert_eq!(user_info.user_email.as_deref(), Some("jane@example.com"));
+    }
+
+    #[test]
+    fn test_stream_query_pairs() {
+        assert_eq!(
+            StreamQuery::new().query_pairs(None),
+            vec![("n", "100".to_string())]
+        );
+
+        let query = StreamQuery::new()
+            .with_count(50)
+            .with_since(Some(1_700_000_000))
+            .with_until(Some(1_800_000_000))
+            .with_oldest_first(true)
+            .with_exclude(Some(StreamId::Read))
+            .with_include(Some(StreamId::Label("Tech".to_string())))
+            .with_annotations(true);
+        assert_eq!(
+            query.query_pairs(Some("gmMZgKmmqI4U")),
+            vec![
+                ("n", "50".to_string()),
+                ("ot", "1700000000".to_string()),
+                ("nt", "1800000000".to_string()),
+                ("r", "o".to_string()),
+                ("xt", "user/-/state/com.google/read".to_string()),
+                ("it", "user/-/label/Tech".to_string()),
+                ("annotations", "1".to_string()),
+                ("c", "gmMZgKmmqI4U".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stream_contents_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config");
+        let repository = ConfigRepository::new(config_path.to_str().unwrap(), "default").unwrap();
+        let client = InoreaderClient::new("id".to_string(), "secret".to_string(), &repository)
+            .with_base_url(DEFAULT_BASE_URL);
+
+        let url = client.stream_contents_url(
+            &StreamId::Label("Tech News".to_string()),
+            &StreamQuery::new().with_exclude(Some(StreamId::Read)),
+            None,
+        );
+        assert_eq!(
+            url,
+            "https://www.inoreader.com/reader/api/0/stream/contents/user%2F-%2Flabel%2FTech%20News?AppId=id&AppKey=secret&n=100&xt=user%2F-%2Fstate%2Fcom.google%2Fread"
+        );
+    }
+
+    #[test]
+    fn test_stream_query_fingerprint() {
+        let query = StreamQuery::new().with_exclude(Some(StreamId::Read));
+        assert_eq!(
+            query.fingerprint(&StreamId::Starred),
+            "user/-/state/com.google/starred?n=100&xt=user%2F-%2Fstate%2Fcom.google%2Fread"
+        );
+        assert_ne!(
+            query.fingerprint(&StreamId::Starred),
+            StreamQuery::new().fingerprint(&StreamId::Starred)
+        );
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(StreamQuery::parse_time("1700000000"), Ok(1_700_000_000));
+        assert_eq!(
+            StreamQuery::parse_time("2026-01-01T00:00:00Z"),
+            Ok(1_767_225_600)
+        );
+        let local_midnight = Local
+            .with_ymd_and_hms(2026, 1, 1, 0, 0, 0)
+            .unwrap()
+            .timestamp();
+        assert_eq!(StreamQuery::parse_time("2026-01-01"), Ok(local_midnight));
+        assert!(StreamQuery::parse_time("yesterday").is_err());
+
+        let now = Local::now().timestamp();
+        let thirty_days_ago = StreamQuery::parse_time("30d").unwrap();
+        assert!((now - 30 * 86_400 - thirty_days_ago).abs() <= 1);
+        let two_weeks_ago = StreamQuery::parse_time("2w").unwrap();
+        assert!((now - 14 * 86_400 - two_weeks_ago).abs() <= 1);
+        assert!(StreamQuery::parse_time("-3d").is_err());
+        assert!(StreamQuery::parse_time("253402300799").is_ok());
+        assert!(StreamQuery::parse_time("253402300800").is_err());
+        assert!(StreamQuery::parse_time("-1").is_err());
+        assert!(StreamQuery::parse_time("9223372036854775807").is_err());
+        assert!(StreamQuery::parse_time("15250284452w").is_err());
+        assert!(StreamQuery::parse_time("d").is_err());
+    }
+
+    #[test]
+    fn test_tokens_expires_soon() {
+        let tokens = Tokens {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            expires_in: 10_000,
+        };
+
+        assert!(!tokens.expires_soon(10_000 - TOKEN_REFRESH_MARGIN_SECS - 1));
+        assert!(tokens.expires_soon(10_000 - TOKEN_REFRESH_MARGIN_SECS));
+        assert!(tokens.expires_soon(20_000));
     }
 }
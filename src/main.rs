@@ -1,25 +1,421 @@
-mod domain;
-mod infrastructure;
-mod repositories;
-mod services;
-use clap::Parser;
+use chrono::{DateTime, Local};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use futures::StreamExt;
 
 use dotenvy::dotenv;
-use infrastructure::InoreaderClient;
-use services::authentication_service::AuthenticationService;
-use services::token_service::TokenService;
+use inoreader_house_cleaning::infrastructure::{
+    FetchLimits, InoreaderClient, RetryPolicy, StopReason, StreamQuery, TagChange,
+};
+use inoreader_house_cleaning::services::authentication_service::AuthenticationService;
+use inoreader_house_cleaning::services::filter_service::Filter;
+use inoreader_house_cleaning::services::token_service::TokenService;
+use std::collections::HashSet;
 use std::env;
-use std::path::Path;
+use std::io::{self, BufRead, Write};
+use std::pin::pin;
 use std::process::exit;
+use std::str::FromStr;
+use std::time::Duration;
 
-use crate::repositories::articles_data_repository::ArticlesDataRepository;
-use crate::repositories::config_repository::ConfigRepository;
-
-const CONFIG_FILE_NAME: &str = ".config";
+use inoreader_house_cleaning::domain::{
+    CredentialStoreKind, Item, ItemWarning, OutputFormat, Profile, Scope, StreamId,
+};
+use inoreader_house_cleaning::error::{describe_wait, Error};
+use inoreader_house_cleaning::repositories::archive_journal_repository::{
+    ArchiveJournal, ArchiveJournalRepository,
+};
+use inoreader_house_cleaning::repositories::articles_data_repository::ArticlesDataRepository;
+use inoreader_house_cleaning::repositories::config_repository::{ConfigError, ConfigRepository};
+use inoreader_house_cleaning::repositories::fetch_state_repository::{
+    FetchState, FetchStateRepository,
+};
+use inoreader_house_cleaning::repositories::undo_journal_repository::{
+    UndoJournalRepository, UndoRun,
+};
 
 #[derive(Parser)]
 struct Cli {
-    action_type: String,
+    /// Config/token file path. Defaults to INOREADER_CONFIG or $XDG_CONFIG_HOME/inoreader/config
+    #[arg(long, global = true)]
+    config: Option<String>,
+
+    /// Profile to use. Defaults to INOREADER_PROFILE or the saved default profile
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    #[command(flatten)]
+    network: NetworkArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct NetworkArgs {
+    /// Attempts per API request; connection errors, timeouts and 5xx responses are retried
+    /// with exponential backoff
+    #[arg(long, global = true, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    max_attempts: u32,
+
+    /// Time limit of a single API request
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 30)]
+    timeout: u64,
+
+    /// When the API quota is used up (HTTP 429), wait for it to reset if that is at most
+    /// this many seconds away; otherwise stop
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 60)]
+    rate_limit_wait: u64,
+}
+
+impl NetworkArgs {
+    fn client(&self, config_repository: &ConfigRepository) -> Result<InoreaderClient, Error> {
+        let (client_id, client_secret) = client_credentials(config_repository.get_profile())?;
+        let retry_policy = RetryPolicy {
+            max_attempts: self.max_attempts,
+            ..RetryPolicy::default()
+        };
+        Ok(
+            InoreaderClient::new(client_id, client_secret, config_repository)
+                .with_retry_policy(retry_policy)
+                .with_request_timeout(Duration::from_secs(self.timeout))
+                .with_max_rate_limit_wait(Duration::from_secs(self.rate_limit_wait)),
+        )
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Authorize this app and save the tokens
+    Setup(AuthArgs),
+    /// Authorize again, e.g. to upgrade the saved token with `--scope write`
+    Reauth(AuthArgs),
+    /// Fetch a stream (starred items by default) and save it in the profile's output format
+    #[command(alias = "fetch_stream")]
+    Fetch(FetchArgs),
+    /// Remove the star from starred items, e.g. after exporting them
+    Unstar(UnstarArgs),
+    /// Mark the unread items of a stream as read, optionally only the older ones
+    MarkRead(MarkReadArgs),
+    /// Append the starred items to an archive file, then unstar them. An interrupted run
+    /// resumes where it stopped
+    Archive(ArchiveArgs),
+    /// Add or remove a label on the items of a stream (starred items by default)
+    Retag(RetagArgs),
+    /// Revert the tag changes of an earlier run; without options, list the recorded runs
+    Undo(UndoArgs),
+    /// Move the profile's saved tokens to another credential store
+    MigrateCredentials {
+        /// Store to write to: file, keyring or encrypted
+        #[arg(long)]
+        to: CredentialStoreKind,
+    },
+    /// Manage named account profiles
+    #[command(subcommand)]
+    Profile(ProfileCommand),
+    /// Inspect or manage the saved tokens
+    #[command(subcommand)]
+    Auth(AuthCommand),
+    /// Inspect the Inoreader API usage
+    #[command(subcommand)]
+    Api(ApiCommand),
+}
+
+#[derive(Subcommand)]
+enum ApiCommand {
+    /// Show today's usage of the Zone 1 (read) and Zone 2 (write) request quotas
+    Quota,
+}
+
+#[derive(Subcommand)]
+enum AuthCommand {
+    /// Show the saved token, its expiry, scope and account
+    Status,
+    /// Refresh the access token now
+    Refresh,
+    /// Delete the saved tokens of the profile
+    Logout,
+}
+
+#[derive(Subcommand)]
+enum ProfileCommand {
+    /// List the saved profiles
+    List,
+    /// Add a profile, or update the given settings of an existing one
+    Add {
+        name: String,
+        #[arg(long)]
+        client_id: Option<String>,
+        #[arg(long)]
+        client_secret: Option<String>,
+        #[arg(long)]
+        redirect_uri: Option<String>,
+        /// Where to keep the tokens: file, keyring or encrypted
+        #[arg(long)]
+        credential_store: Option<CredentialStoreKind>,
+        /// Default output format: csv or json
+        #[arg(long)]
+        output_format: Option<OutputFormat>,
+        /// Default output file
+        #[arg(long)]
+        output_path: Option<String>,
+        /// Make this the default profile
+        #[arg(long)]
+        default: bool,
+    },
+    /// Remove a profile and its saved tokens
+    Remove { name: String },
+    /// Set the default profile
+    Default { name: String },
+}
+
+#[derive(Args)]
+struct FetchArgs {
+    #[command(flatten)]
+    source: StreamSource,
+
+    /// Items per request (1-1000)
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    count: u32,
+
+    /// Only items newer than this: YYYY-MM-DD (local time), RFC 3339, a Unix time or an age like 30d
+    #[arg(long, value_name = "TIME", value_parser = StreamQuery::parse_time)]
+    since: Option<i64>,
+
+    /// Only items older than this: YYYY-MM-DD (local time), RFC 3339, a Unix time or an age like 30d
+    #[arg(long, value_name = "TIME", value_parser = StreamQuery::parse_time)]
+    until: Option<i64>,
+
+    /// Leave out read items, same as `--exclude read`
+    #[arg(long, conflicts_with = "exclude")]
+    unread_only: bool,
+
+    /// Return the oldest items first
+    #[arg(long)]
+    oldest_first: bool,
+
+    /// Leave out items that are also in this stream
+    #[arg(long)]
+    exclude: Option<StreamId>,
+
+    /// Only items that are also in this stream
+    #[arg(long)]
+    include: Option<StreamId>,
+
+    /// Include highlights and notes
+    #[arg(long)]
+    annotations: bool,
+
+    #[command(flatten)]
+    filter: WhereArg,
+
+    /// Stop after listing this many items, counted before --where is applied (default: no limit)
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    limit: Option<usize>,
+
+    /// Stop after this many requests (default: no limit)
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_pages: Option<usize>,
+
+    /// Continue where the last stopped run of the same stream and options left off,
+    /// appending to the output file
+    #[arg(long)]
+    resume: bool,
+}
+
+impl FetchArgs {
+    fn query(&self) -> StreamQuery {
+        let exclude = match self.unread_only {
+            true => Some(StreamId::Read),
+            false => self.exclude.clone(),
+        };
+        StreamQuery::new()
+            .with_count(self.count)
+            .with_since(self.since)
+            .with_until(self.until)
+            .with_oldest_first(self.oldest_first)
+            .with_exclude(exclude)
+            .with_include(self.include.clone())
+            .with_annotations(self.annotations)
+    }
+
+    fn limits(&self) -> FetchLimits {
+        FetchLimits {
+            max_items: self.limit,
+            max_pages: self.max_pages,
+        }
+    }
+}
+
+#[derive(Args)]
+struct WhereArg {
+    /// Only items matching this expression, e.g. `feed ~ "arstechnica" and age > 90d and not label:keep`
+    #[arg(long = "where", value_name = "EXPR", value_parser = Filter::from_str)]
+    filter: Option<Filter>,
+}
+
+impl WhereArg {
+    fn is_set(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    /// Whether `item` matches the expression, if one was given.
+    fn matches(&self, item: &Item, now: i64) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(item, now))
+    }
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct StreamSource {
+    /// Stream ID or state: reading-list, starred, read, annotated, broadcast, user/-/label/<name>, feed/<url>
+    #[arg(long)]
+    stream: Option<StreamId>,
+
+    /// Label (folder or tag) name, same as `--stream user/-/label/<name>`
+    #[arg(long)]
+    label: Option<String>,
+
+    /// Feed URL, same as `--stream feed/<url>`
+    #[arg(long)]
+    feed: Option<String>,
+}
+
+impl StreamSource {
+    fn stream_id(&self) -> StreamId {
+        match (&self.stream, &self.label, &self.feed) {
+            (Some(stream), _, _) => stream.clone(),
+            (_, Some(label), _) => StreamId::Label(label.clone()),
+            (_, _, Some(feed)) => StreamId::Feed(feed.clone()),
+            _ => StreamId::Starred,
+        }
+    }
+}
+
+#[derive(Args)]
+#[command(group(
+    ArgGroup::new("selection")
+        .required(true)
+        .multiple(true)
+        .args(["all", "older_than", "feed", "filter", "from_export"])
+))]
+struct UnstarArgs {
+    /// Unstar every starred item
+    #[arg(long, conflicts_with_all = ["older_than", "feed", "filter", "from_export"])]
+    all: bool,
+
+    /// Only items older than this: YYYY-MM-DD (local time), RFC 3339, a Unix time or an age like 90d
+    #[arg(long, value_name = "TIME", value_parser = StreamQuery::parse_time)]
+    older_than: Option<i64>,
+
+    /// Only items of this feed URL
+    #[arg(long)]
+    feed: Option<String>,
+
+    #[command(flatten)]
+    filter: WhereArg,
+
+    /// Unstar the items of an earlier export or archive (CSV, JSON or JSON Lines)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["older_than", "feed", "filter"])]
+    from_export: Option<String>,
+
+    /// List the items that would be unstarred without changing anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct MarkReadArgs {
+    /// Stream ID or state: reading-list, user/-/label/<name>, feed/<url>, ...
+    #[arg(long)]
+    stream: StreamId,
+
+    /// Only items older than this: YYYY-MM-DD (local time), RFC 3339, a Unix time or an age like 30d
+    #[arg(long, value_name = "TIME", value_parser = StreamQuery::parse_time)]
+    older_than: Option<i64>,
+
+    #[command(flatten)]
+    filter: WhereArg,
+
+    /// List the unread items and mark them in batches with edit-tag instead of a single
+    /// mark-all-as-read request. Implied by --where
+    #[arg(long)]
+    per_item: bool,
+
+    /// Do not ask for confirmation
+    #[arg(long, short)]
+    yes: bool,
+}
+
+#[derive(Args)]
+struct ArchiveArgs {
+    /// Archive format: csv or json (written as JSON Lines). Defaults to the profile's output format
+    #[arg(long)]
+    format: Option<OutputFormat>,
+
+    /// Archive file; items are appended to it. Defaults to archive.csv or archive.jsonl
+    #[arg(long, value_name = "FILE")]
+    output: Option<String>,
+
+    #[command(flatten)]
+    filter: WhereArg,
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("change").required(true).multiple(true).args(["add", "remove"])))]
+struct RetagArgs {
+    #[command(flatten)]
+    source: StreamSource,
+
+    #[command(flatten)]
+    filter: WhereArg,
+
+    /// Label to add
+    #[arg(long, value_name = "LABEL")]
+    add: Option<String>,
+
+    /// Label to remove
+    #[arg(long, value_name = "LABEL")]
+    remove: Option<String>,
+
+    /// List the items that would be changed without changing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Do not ask for confirmation
+    #[arg(long, short)]
+    yes: bool,
+}
+
+#[derive(Args)]
+struct UndoArgs {
+    /// Revert the newest run that has not been undone
+    #[arg(long, conflicts_with = "run")]
+    last: bool,
+
+    /// Revert this run
+    #[arg(long, value_name = "ID")]
+    run: Option<String>,
+
+    /// Do not ask for confirmation
+    #[arg(long, short)]
+    yes: bool,
+}
+
+#[derive(Args)]
+struct AuthArgs {
+    /// Paste the authorization code instead of starting the local callback listener
+    #[arg(long)]
+    manual: bool,
+
+    /// Use PKCE (S256) in the authorization request. Can also be enabled with INOREADER_USE_PKCE=1
+    #[arg(long)]
+    pkce: bool,
+
+    /// OAuth scope to request: `read` or `write` (read + write). Defaults to INOREADER_SCOPE or `read`
+    #[arg(long)]
+    scope: Option<Scope>,
 }
 
 #[tokio::main]
@@ -27,59 +423,1115 @@ async fn main() {
     dotenv().ok();
     let args = Cli::parse();
 
-    let client_id = env::var("INOREADER_CLIENT_ID").expect("INOREADER_CLIENT_ID is not set");
-    let client_secret =
-        env::var("INOREADER_CLIENT_SECRET").expect("INOREADER_CLIENT_SECRET is not set");
+    if let Err(e) = run(args).await {
+        eprintln!("Error: {}", e);
+        exit(e.exit_code());
+    }
+}
 
-    match args.action_type.as_str() {
-        "setup" => {
-            if exist_token_file() {
-                eprintln!("Token file already exists.");
-                exit(1);
+async fn run(args: Cli) -> Result<(), Error> {
+    let config_path = ConfigRepository::resolve_config_path(args.config.as_deref());
+    if ConfigRepository::upgrade_config_format(&config_path)? {
+        println!("Converted {} to the TOML config format", config_path);
+    }
+    match ConfigRepository::migrate_legacy_config(&config_path) {
+        Ok(true) => println!("Moved the legacy .config token file to {}", config_path),
+        Ok(false) => {}
+        Err(e) => eprintln!("Failed to migrate the legacy .config token file: {}", e),
+    }
+
+    let profile_name =
+        ConfigRepository::resolve_profile_name(&config_path, args.profile.as_deref());
+
+    match args.command {
+        Command::Profile(profile_command) => run_profile_command(&config_path, profile_command),
+        Command::Auth(auth_command) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            run_auth_command(config_repository, auth_command, &args.network).await
+        }
+        Command::Api(api_command) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            run_api_command(&config_repository, api_command, &args.network).await
+        }
+        Command::MigrateCredentials { to } => {
+            let mut config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            let from = config_repository.get_profile().credential_store;
+            if from == to {
+                return Err(Error::Usage(format!(
+                    "profile '{}' already uses the {} store",
+                    profile_name, to
+                )));
             }
 
-            let redirect_uri =
-                env::var("INOREADER_REDIRECT_URI").expect("INOREADER_REDIRECT_URI is not set");
+            if !config_repository.migrate_credentials(to)? {
+                return Err(Error::Usage(format!(
+                    "no credentials found in the {} store",
+                    from
+                )));
+            }
 
-            AuthenticationService::app_authenticate(&client_id, &client_secret, &redirect_uri)
-                .await
-                .expect("Authentication failed");
+            println!(
+                "Moved the credentials of profile '{}' from {} to {}.",
+                profile_name, from, to
+            );
+            Ok(())
         }
-        "fetch_stream" => {
-            if !exist_token_file() {
-                eprintln!("Token file not found. Please run 'setup' command first.");
-                exit(1);
+        Command::Setup(auth_args) => {
+            if ConfigRepository::profile_has_saved_tokens(&config_path, &profile_name)? {
+                return Err(Error::Usage(
+                    "the profile is already authorized; use 'reauth' to authorize again"
+                        .to_string(),
+                ));
             }
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            ConfigRepository::save_profile(&config_path, config_repository.get_profile())?;
 
-            let config_repository = ConfigRepository::new(CONFIG_FILE_NAME);
-            let refresh_token = config_repository.get_saved_refresh_token();
+            authenticate(&config_repository, &auth_args).await
+        }
+        Command::Reauth(auth_args) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            config_repository.require_tokens()?;
+            println!(
+                "Current scope: {}",
+                config_repository.get_saved_scope().as_str()
+            );
 
-            if config_repository.is_token_expired() {
-                TokenService::new(&client_id, &client_secret, &refresh_token)
-                    .refreshing_token()
-                    .await
-                    .expect("Failed to refresh token");
+            authenticate(&config_repository, &auth_args).await
+        }
+        Command::Fetch(fetch_args) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            config_repository.require_tokens()?;
+            run_fetch(&config_repository, &fetch_args, &args.network).await
+        }
+        Command::Unstar(unstar_args) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            config_repository.require_tokens()?;
+            if !unstar_args.dry_run {
+                config_repository.require_scope(Scope::ReadWrite)?;
             }
+            run_unstar(&config_repository, &unstar_args, &args.network).await
+        }
+        Command::MarkRead(mark_read_args) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            config_repository.require_tokens()?;
+            config_repository.require_scope(Scope::ReadWrite)?;
+            run_mark_read(&config_repository, &mark_read_args, &args.network).await
+        }
+        Command::Archive(archive_args) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            config_repository.require_tokens()?;
+            config_repository.require_scope(Scope::ReadWrite)?;
+            run_archive(&config_repository, &archive_args, &args.network).await
+        }
+        Command::Retag(retag_args) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            config_repository.require_tokens()?;
+            if !retag_args.dry_run {
+                config_repository.require_scope(Scope::ReadWrite)?;
+            }
+            run_retag(&config_repository, &retag_args, &args.network).await
+        }
+        Command::Undo(undo_args) => {
+            let config_repository = ConfigRepository::new(&config_path, &profile_name)?;
+            run_undo(&config_repository, &undo_args, &args.network).await
+        }
+    }
+}
 
-            let access_token = config_repository.get_saved_access_token();
-            let client = InoreaderClient::new(client_id, client_secret, access_token.to_string());
-            let articles_data = client
-                .fetch_stream_contents()
-                .await
-                .expect("Failed to fetch contents");
+fn run_profile_command(config_path: &str, command: ProfileCommand) -> Result<(), Error> {
+    match command {
+        ProfileCommand::List => {
+            let (profiles, default) = ConfigRepository::list_profiles(config_path)?;
+            if profiles.is_empty() {
+                println!("No profiles. Use 'profile add <name>' or 'setup' to create one.");
+            }
+            for profile in profiles {
+                let marker = if profile.name == default { "*" } else { " " };
+                let authorized =
+                    ConfigRepository::profile_has_saved_tokens(config_path, &profile.name)?;
+                println!(
+                    "{} {} (store: {}, output: {} -> {}, {})",
+                    marker,
+                    profile.name,
+                    profile.credential_store,
+                    profile.output_format,
+                    profile.output_path(),
+                    if authorized {
+                        "authorized"
+                    } else {
+                        "not authorized"
+                    }
+                );
+            }
+        }
+        ProfileCommand::Add {
+            name,
+            client_id,
+            client_secret,
+            redirect_uri,
+            credential_store,
+            output_format,
+            output_path,
+            default,
+        } => {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(Error::Usage(
+                    "profile names may only contain letters, digits, '-' and '_'".to_string(),
+                ));
+            }
 
-            ArticlesDataRepository::save_articles_to_csv(&articles_data)
-                .expect("Failed to save articles to csv");
+            let (profiles, _) = ConfigRepository::list_profiles(config_path)?;
+            let mut profile = match profiles.into_iter().find(|profile| profile.name == name) {
+                Some(profile) => profile,
+                None => Profile::new(&name, CredentialStoreKind::from_env()?),
+            };
 
-            println!("Done!");
+            if let Some(kind) = credential_store {
+                if kind != profile.credential_store
+                    && ConfigRepository::profile_has_saved_credentials(config_path, &name)?
+                {
+                    return Err(Error::Usage(format!(
+                        "profile '{}' has saved credentials; use 'migrate-credentials' to change its store",
+                        name
+                    )));
+                }
+                profile.credential_store = kind;
+            }
+            profile.client_id = client_id.or(profile.client_id);
+            profile.client_secret = client_secret.or(profile.client_secret);
+            profile.redirect_uri = redirect_uri.or(profile.redirect_uri);
+            profile.output_format = output_format.unwrap_or(profile.output_format);
+            profile.output_path = output_path.or(profile.output_path);
+
+            ConfigRepository::save_profile(config_path, &profile)?;
+            if default {
+                ConfigRepository::set_default_profile(config_path, &name)?;
+            }
+            println!("Saved profile '{}'.", name);
+        }
+        ProfileCommand::Remove { name } => {
+            if !ConfigRepository::remove_profile(config_path, &name)? {
+                return Err(ConfigError::UnknownProfile(name).into());
+            }
+            println!("Removed profile '{}'.", name);
         }
-        _ => {
-            eprintln!("Invalid command. Use 'setup' or 'fetch_stream'.");
+        ProfileCommand::Default { name } => {
+            ConfigRepository::set_default_profile(config_path, &name)?;
+            println!("Default profile is now '{}'.", name);
         }
     }
+    Ok(())
+}
+
+async fn run_fetch(
+    config_repository: &ConfigRepository,
+    fetch_args: &FetchArgs,
+    network: &NetworkArgs,
+) -> Result<(), Error> {
+    let profile = config_repository.get_profile();
+    let stream = fetch_args.source.stream_id();
+    let query = fetch_args.query();
+
+    let state_repository = FetchStateRepository::new(config_repository.get_config_path());
+    let mut state_key = format!("{}:{}", profile.name, query.fingerprint(&stream));
+    // 絞り込みの違う実行の続きを同じファイルに追記しないよう、式もキーに含める
+    if let Some(filter) = &fetch_args.filter.filter {
+        state_key.push_str(&format!(" where {}", filter));
+    }
+    let resume_from = match fetch_args.resume {
+        true => state_repository.load(&state_key)?,
+        false => None,
+    };
+    match &resume_from {
+        Some(state) => println!("Resuming after {} items saved earlier.", state.fetched),
+        None if fetch_args.resume => {
+            println!("No saved position for this stream; starting from the beginning.")
+        }
+        None => {}
+    }
+
+    let client = network.client(config_repository)?;
+    let mut fetched = client
+        .fetch_stream_contents(
+            &stream,
+            &query,
+            fetch_args.limits(),
+            resume_from.as_ref().map(|state| state.continuation.clone()),
+        )
+        .await;
+
+    // 1 件も取れずに失敗した場合は既存の出力を上書きしない
+    if fetched.articles.is_empty() {
+        if let Some(StopReason::Failed(e)) = fetched.stopped {
+            return Err(e);
+        }
+    }
+    if fetch_args.filter.is_set() {
+        let listed = fetched.articles.len();
+        let now = Local::now().timestamp();
+        fetched
+            .articles
+            .retain(|item| fetch_args.filter.matches(item, now));
+        println!(
+            "{} of {} items match --where.",
+            fetched.articles.len(),
+            listed
+        );
+    }
+
+    let append = resume_from.is_some();
+    match profile.output_format {
+        OutputFormat::Csv => ArticlesDataRepository::save_articles_to_csv(
+            &fetched.articles,
+            profile.output_path(),
+            append,
+        )?,
+        OutputFormat::Json => ArticlesDataRepository::save_articles_to_json(
+            &fetched.articles,
+            profile.output_path(),
+            append,
+        )?,
+    }
+    report_item_warnings(&fetched.warnings);
+
+    let saved = resume_from.map_or(0, |state| state.fetched) + fetched.articles.len();
+    let reason = match fetched.stopped {
+        Some(reason) => reason,
+        None => {
+            state_repository.clear(&state_key)?;
+            println!(
+                "Done! Saved {} items to {}",
+                fetched.articles.len(),
+                profile.output_path()
+            );
+            return Ok(());
+        }
+    };
+
+    match fetched.continuation {
+        Some(continuation) => {
+            state_repository.save(
+                &state_key,
+                &FetchState {
+                    continuation,
+                    fetched: saved,
+                },
+            )?;
+            eprintln!(
+                "Stopped before the end of the stream ({}) after {} pages. Saved {} items to {}; run again with --resume to continue.",
+                reason,
+                fetched.pages,
+                fetched.articles.len(),
+                profile.output_path()
+            );
+        }
+        None => eprintln!(
+            "Stopped before the end of the stream ({}). Saved {} items to {}.",
+            reason,
+            fetched.articles.len(),
+            profile.output_path()
+        ),
+    }
+    match reason {
+        StopReason::Failed(e) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+async fn run_unstar(
+    config_repository: &ConfigRepository,
+    unstar_args: &UnstarArgs,
+    network: &NetworkArgs,
+) -> Result<(), Error> {
+    let client = network.client(config_repository)?;
+
+    // (ID, タイトル)。エクスポートファイルからの場合タイトルは分からない
+    let selected: Vec<(String, String)> = match &unstar_args.from_export {
+        Some(path) => {
+            let mut seen = HashSet::new();
+            ArticlesDataRepository::load_item_ids(path)?
+                .into_iter()
+                .filter(|id| seen.insert(id.clone()))
+                .map(|id| (id, String::new()))
+                .collect()
+        }
+        None => {
+            let (stream, include) = match &unstar_args.feed {
+                Some(feed) => (StreamId::Feed(feed.clone()), Some(StreamId::Starred)),
+                None => (StreamId::Starred, None),
+            };
+            let query = StreamQuery::new()
+                .with_count(1000)
+                .with_until(unstar_args.older_than)
+                .with_include(include);
+            let fetched = client
+                .fetch_stream_contents(&stream, &query, FetchLimits::default(), None)
+                .await;
+            report_item_warnings(&fetched.warnings);
+            if let Some(StopReason::Failed(e)) = fetched.stopped {
+                eprintln!("Could not list the starred items; nothing was unstarred.");
+                return Err(e);
+            }
+            let now = Local::now().timestamp();
+            fetched
+                .articles
+                .into_iter()
+                .filter(|item| unstar_args.filter.matches(item, now))
+                .map(|item| (item.id, item.title))
+                .collect()
+        }
+    };
+
+    if selected.is_empty() {
+        println!("No items to unstar.");
+        return Ok(());
+    }
+    if unstar_args.dry_run {
+        println!("Would unstar {} items:", selected.len());
+        for (id, title) in &selected {
+            println!("  {} {}", id, title);
+        }
+        return Ok(());
+    }
+
+    let ids: Vec<String> = selected.into_iter().map(|(id, _)| id).collect();
+    let undo_journal = undo_journal(config_repository);
+    let run = UndoRun::new("unstar");
+    let mut unstarred = 0;
+    let result = edit_tags(
+        &client,
+        &undo_journal,
+        &run,
+        &ids,
+        &TagChange::remove(StreamId::Starred),
+        |batch| {
+            unstarred += batch.len();
+            println!("Unstarred {} of {} items", unstarred, ids.len());
+            Ok(())
+        },
+    )
+    .await;
+
+    match &result {
+        Ok(()) => println!("Done! Unstarred {} items.", unstarred),
+        Err(_) => eprintln!(
+            "Stopped after unstarring {} of {} items.",
+            unstarred,
+            ids.len()
+        ),
+    }
+    report_undo_run(&run, unstarred);
+    result
+}
+
+async fn run_mark_read(
+    config_repository: &ConfigRepository,
+    mark_read_args: &MarkReadArgs,
+    network: &NetworkArgs,
+) -> Result<(), Error> {
+    let client = network.client(config_repository)?;
+    let stream = &mark_read_args.stream;
+    let older_than = mark_read_args
+        .older_than
+        .unwrap_or_else(|| Local::now().timestamp());
+
+    let counts = client.unread_count().await?;
+    let unread = counts.of(stream);
+    println!("{}: {} unread items", stream, counts.describe(unread));
+    if unread == 0 {
+        println!("Nothing to mark as read.");
+        return Ok(());
+    }
+
+    // unread-count は期限で絞れないので、--per-item のときだけ正確な件数が分かる
+    let item_ids = match mark_read_args.per_item || mark_read_args.filter.is_set() {
+        true => {
+            let query = StreamQuery::new()
+                .with_count(1000)
+                .with_until(Some(older_than))
+                .with_exclude(Some(StreamId::Read));
+            let fetched = client
+                .fetch_stream_contents(stream, &query, FetchLimits::default(), None)
+                .await;
+            report_item_warnings(&fetched.warnings);
+            if let Some(StopReason::Failed(e)) = fetched.stopped {
+                eprintln!("Could not list the unread items; nothing was marked as read.");
+                return Err(e);
+            }
+            let now = Local::now().timestamp();
+            let ids: Vec<String> = fetched
+                .articles
+                .into_iter()
+                .filter(|item| mark_read_args.filter.matches(item, now))
+                .map(|item| item.id)
+                .collect();
+            if ids.is_empty() {
+                println!("No unread items older than {}.", describe_time(older_than));
+                return Ok(());
+            }
+            println!(
+                "{} of them are older than {}.",
+                ids.len(),
+                describe_time(older_than)
+            );
+            Some(ids)
+        }
+        false => {
+            if mark_read_args.older_than.is_some() {
+                println!(
+                    "Only the items older than {} will be marked as read.",
+                    describe_time(older_than)
+                );
+            }
+            println!("This cannot be undone; use --per-item to record the items for 'undo'.");
+            None
+        }
+    };
+
+    if !mark_read_args.yes && !confirm("Mark them as read?")? {
+        println!("Nothing was marked as read.");
+        return Ok(());
+    }
+
+    let undo_journal = undo_journal(config_repository);
+    let run = UndoRun::new("mark-read");
+    let ids = match item_ids {
+        Some(ids) => ids,
+        None => {
+            client.mark_all_as_read(stream, older_than).await?;
+            undo_journal.record_stream_read(&run, &stream.as_id(), &StreamId::Read.as_id())?;
+            println!("Done! Marked the items of {} as read.", stream);
+            return Ok(());
+        }
+    };
+    let mut marked = 0;
+    let result = edit_tags(
+        &client,
+        &undo_journal,
+        &run,
+        &ids,
+        &TagChange::add(StreamId::Read),
+        |batch| {
+            marked += batch.len();
+            println!("Marked {} of {} items as read", marked, ids.len());
+            Ok(())
+        },
+    )
+    .await;
+
+    match &result {
+        Ok(()) => println!("Done! Marked {} items as read.", marked),
+        Err(_) => eprintln!(
+            "Stopped after marking {} of {} items as read.",
+            marked,
+            ids.len()
+        ),
+    }
+    report_undo_run(&run, marked);
+    result
+}
+
+async fn run_archive(
+    config_repository: &ConfigRepository,
+    archive_args: &ArchiveArgs,
+    network: &NetworkArgs,
+) -> Result<(), Error> {
+    let profile = config_repository.get_profile();
+    let journal_repository =
+        ArchiveJournalRepository::new(config_repository.get_config_path(), &profile.name);
+
+    let journal = match journal_repository.load()? {
+        Some(journal) => {
+            println!(
+                "Resuming the interrupted run: {} items archived to {}, {} of them still starred.",
+                journal.archived.len(),
+                journal.output_path,
+                journal.pending().len()
+            );
+            if archive_args.output.is_some() || archive_args.format.is_some() {
+                eprintln!(
+                    "Ignoring --output and --format; the run continues with its archive file."
+                );
+            }
+            journal
+        }
+        None => {
+            let output_format = archive_args.format.unwrap_or(profile.output_format);
+            let output_path = archive_args
+                .output
+                .as_deref()
+                .unwrap_or(output_format.default_archive_path());
+            // 別のディレクトリから再開しても同じファイルに追記できるよう絶対パスで記録する
+            let output_path = std::path::absolute(output_path)?
+                .to_string_lossy()
+                .into_owned();
+            journal_repository.start(&output_path, output_format)?;
+            ArchiveJournal {
+                output_path,
+                output_format,
+                archived: Vec::new(),
+                unstarred: HashSet::new(),
+            }
+        }
+    };
+    let output_path = journal.output_path.clone();
+
+    let client = network.client(config_repository)?;
+    let undo_journal = undo_journal(config_repository);
+    let run = UndoRun::new("archive");
+    let result = archive_starred(
+        &client,
+        &journal_repository,
+        &undo_journal,
+        &run,
+        journal,
+        &archive_args.filter,
+    )
+    .await;
+    report_undo_run(&run, undo_journal.run(&run.id)?.len());
+
+    match result {
+        Ok(count) => {
+            journal_repository.clear()?;
+            println!(
+                "Done! Archived and unstarred {} items ({}).",
+                count, output_path
+            );
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Stopped; run 'archive' again to resume. Nothing was unstarred before it was archived.");
+            Err(e)
+        }
+    }
+}
+
+/// Unstars the items archived by an earlier run, then lists the starred items that match
+/// `filter` and archives and unstars them page by page. Returns how many items were unstarred.
+async fn archive_starred(
+    client: &InoreaderClient,
+    journal_repository: &ArchiveJournalRepository,
+    undo_journal: &UndoJournalRepository,
+    run: &UndoRun,
+    journal: ArchiveJournal,
+    filter: &WhereArg,
+) -> Result<usize, Error> {
+    let pending = journal.pending();
+    unstar_archived(client, journal_repository, undo_journal, run, &pending).await?;
+    let mut count = pending.len();
+    let mut archived: HashSet<String> = journal.archived.into_iter().collect();
+
+    // continuation は一覧中のストリームが変わると記事を飛ばしうるので、スターを外す前に最後まで一覧する
+    let query = StreamQuery::new();
+    let now = Local::now().timestamp();
+    let mut to_archive: Vec<Vec<Item>> = Vec::new();
+    let mut pages =
+        pin!(client.stream_pages(&StreamId::Starred, &query, FetchLimits::default(), None));
+    while let Some(page) = pages.next().await {
+        let page = page?;
+        report_item_warnings(&page.warnings);
+        // 前回アーカイブ済みでスター解除に失敗したものは二重に書かない
+        let items: Vec<Item> = page
+            .items
+            .into_iter()
+            .filter(|item| filter.matches(item, now))
+            .filter(|item| archived.insert(item.id.clone()))
+            .collect();
+        if !items.is_empty() {
+            to_archive.push(items);
+        }
+    }
+
+    for items in to_archive {
+        // 書き込みは fsync まで済んでから返るので、その後に記録したものだけスターを外す
+        match journal.output_format {
+            OutputFormat::Csv => {
+                ArticlesDataRepository::save_articles_to_csv(&items, &journal.output_path, true)?
+            }
+            OutputFormat::Json => {
+                ArticlesDataRepository::append_articles_to_json_lines(&items, &journal.output_path)?
+            }
+        }
+        let ids: Vec<String> = items.into_iter().map(|item| item.id).collect();
+        journal_repository.record_archived(&ids)?;
+
+        unstar_archived(client, journal_repository, undo_journal, run, &ids).await?;
+        count += ids.len();
+        println!("Archived and unstarred {} items", count);
+    }
+    Ok(count)
+}
+
+/// Unstars archived items in batches, recording each batch in the journal.
+async fn unstar_archived(
+    client: &InoreaderClient,
+    journal_repository: &ArchiveJournalRepository,
+    undo_journal: &UndoJournalRepository,
+    run: &UndoRun,
+    ids: &[String],
+) -> Result<(), Error> {
+    let change = TagChange::remove(StreamId::Starred);
+    edit_tags(client, undo_journal, run, ids, &change, |batch| {
+        Ok(journal_repository.record_unstarred(batch)?)
+    })
+    .await
+}
+
+async fn run_retag(
+    config_repository: &ConfigRepository,
+    retag_args: &RetagArgs,
+    network: &NetworkArgs,
+) -> Result<(), Error> {
+    let client = network.client(config_repository)?;
+    let stream = retag_args.source.stream_id();
+    let change = TagChange {
+        add: retag_args.add.clone().map(StreamId::Label),
+        remove: retag_args.remove.clone().map(StreamId::Label),
+    };
+
+    let query = StreamQuery::new().with_count(1000);
+    let fetched = client
+        .fetch_stream_contents(&stream, &query, FetchLimits::default(), None)
+        .await;
+    report_item_warnings(&fetched.warnings);
+    if let Some(StopReason::Failed(e)) = fetched.stopped {
+        eprintln!(
+            "Could not list the items of {}; nothing was changed.",
+            stream
+        );
+        return Err(e);
+    }
+    let now = Local::now().timestamp();
+    let selected: Vec<Item> = fetched
+        .articles
+        .into_iter()
+        .filter(|item| retag_args.filter.matches(item, now))
+        .collect();
+
+    if selected.is_empty() {
+        println!("No items to retag.");
+        return Ok(());
+    }
+    println!("{} ({} items):", describe_change(&change), selected.len());
+    if retag_args.dry_run {
+        for item in &selected {
+            println!("  {} {}", item.id, item.title);
+        }
+        return Ok(());
+    }
+    if !retag_args.yes && !confirm("Change these items?")? {
+        println!("Nothing was changed.");
+        return Ok(());
+    }
+
+    let ids: Vec<String> = selected.into_iter().map(|item| item.id).collect();
+    let undo_journal = undo_journal(config_repository);
+    let run = UndoRun::new("retag");
+    let mut retagged = 0;
+    let result = edit_tags(&client, &undo_journal, &run, &ids, &change, |batch| {
+        retagged += batch.len();
+        println!("Retagged {} of {} items", retagged, ids.len());
+        Ok(())
+    })
+    .await;
+
+    match &result {
+        Ok(()) => println!("Done! Retagged {} items.", retagged),
+        Err(_) => eprintln!(
+            "Stopped after retagging {} of {} items.",
+            retagged,
+            ids.len()
+        ),
+    }
+    report_undo_run(&run, retagged);
+    result
+}
+
+async fn run_undo(
+    config_repository: &ConfigRepository,
+    undo_args: &UndoArgs,
+    network: &NetworkArgs,
+) -> Result<(), Error> {
+    let undo_journal = undo_journal(config_repository);
+    let run_id = match (&undo_args.run, undo_args.last) {
+        (Some(run), _) => run.clone(),
+        (None, true) => match undo_journal.last_undoable_run()? {
+            Some(run) => run,
+            None => {
+                println!("Nothing to undo.");
+                return Ok(());
+            }
+        },
+        (None, false) => {
+            let runs = undo_journal.runs()?;
+            if runs.is_empty() {
+                println!("No changes recorded.");
+            }
+            for run in runs {
+                let what = match run.streams.is_empty() {
+                    true => format!("{} items", run.items),
+                    false => format!(
+                        "mark-all-as-read of {} (cannot be undone)",
+                        run.streams.join(", ")
+                    ),
+                };
+                println!(
+                    "{}  {}  {:<10} {}{}",
+                    run.id,
+                    describe_time(run.at),
+                    run.command,
+                    what,
+                    if run.undone { " [undone]" } else { "" }
+                );
+            }
+            return Ok(());
+        }
+    };
+
+    let mutations = undo_journal.run(&run_id)?;
+    let first = match mutations.first() {
+        Some(first) => first,
+        None => {
+            return Err(Error::Usage(format!(
+                "no run '{}' in the undo journal; run 'undo' to list the runs",
+                run_id
+            )));
+        }
+    };
+    for stream in mutations
+        .iter()
+        .filter_map(|mutation| mutation.stream.as_ref())
+    {
+        eprintln!(
+            "{} was marked as read with mark-all-as-read; its items were not recorded and stay read.",
+            stream
+        );
+    }
+
+    // 新しい変更から順に戻す。同じ変更の記事はまとめて送る
+    let mut inverses: Vec<(TagChange, Vec<String>)> = Vec::new();
+    for (item, change) in mutations
+        .iter()
+        .rev()
+        .filter_map(|mutation| mutation.inverse())
+    {
+        match inverses.iter_mut().find(|(inverse, _)| *inverse == change) {
+            Some((_, items)) => items.push(item),
+            None => inverses.push((change, vec![item])),
+        }
+    }
+    if inverses.is_empty() {
+        println!("Nothing to undo in run {}.", run_id);
+        return Ok(());
+    }
+
+    println!(
+        "Run {} ({}, {}):",
+        run_id,
+        first.command,
+        describe_time(first.at)
+    );
+    for (change, items) in &inverses {
+        println!("  {}: {} items", describe_change(change), items.len());
+    }
+    if !undo_args.yes && !confirm("Revert these changes?")? {
+        println!("Nothing was changed.");
+        return Ok(());
+    }
+
+    config_repository.require_tokens()?;
+    config_repository.require_scope(Scope::ReadWrite)?;
+    let client = network.client(config_repository)?;
+    let run = UndoRun::undoing(&run_id);
+    let total: usize = inverses.iter().map(|(_, items)| items.len()).sum();
+    let mut reverted = 0;
+    for (change, items) in &inverses {
+        let result = edit_tags(&client, &undo_journal, &run, items, change, |batch| {
+            reverted += batch.len();
+            println!("Reverted {} of {} changes", reverted, total);
+            Ok(())
+        })
+        .await;
+        if let Err(e) = result {
+            eprintln!(
+                "Stopped after reverting {} of {} changes; run 'undo --run {}' again to finish.",
+                reverted, total, run_id
+            );
+            return Err(e);
+        }
+    }
+    println!("Done! Reverted run {}.", run_id);
+    report_undo_run(&run, reverted);
+    Ok(())
+}
+
+/// Describes a tag change, e.g. `add user/-/state/com.google/starred`.
+fn describe_change(change: &TagChange) -> String {
+    let tags = [("add", &change.add), ("remove", &change.remove)];
+    let parts: Vec<String> = tags
+        .iter()
+        .filter_map(|(verb, tag)| tag.as_ref().map(|tag| format!("{} {}", verb, tag)))
+        .collect();
+    parts.join(", ")
+}
+
+/// Applies `change` to the items in batches. Each batch is recorded in the undo journal under
+/// `run` as soon as it is sent, then passed to `on_batch`.
+async fn edit_tags(
+    client: &InoreaderClient,
+    undo_journal: &UndoJournalRepository,
+    run: &UndoRun,
+    item_ids: &[String],
+    change: &TagChange,
+    mut on_batch: impl FnMut(&[String]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut batches = pin!(client.edit_tag(item_ids, change));
+    while let Some(batch) = batches.next().await {
+        let batch = batch?;
+        undo_journal.record(run, change, batch)?;
+        on_batch(batch)?;
+    }
+    Ok(())
+}
+
+fn undo_journal(config_repository: &ConfigRepository) -> UndoJournalRepository {
+    UndoJournalRepository::new(
+        config_repository.get_config_path(),
+        &config_repository.get_profile().name,
+    )
+}
+
+fn report_undo_run(run: &UndoRun, changed: usize) {
+    if changed > 0 {
+        println!(
+            "Recorded as run {}; 'undo --run {}' reverts it.",
+            run.id, run.id
+        );
+    }
+}
+
+/// Asks a yes/no question on the terminal; anything but `y` or `yes` is a no.
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn report_item_warnings(warnings: &[ItemWarning]) {
+    if warnings.is_empty() {
+        return;
+    }
+    let skipped = warnings.iter().filter(|w| w.is_skipped()).count();
+    eprintln!(
+        "Skipped {} unreadable items; {} other item warnings:",
+        skipped,
+        warnings.len() - skipped
+    );
+    for warning in warnings {
+        eprintln!("  {}", warning);
+    }
+}
+
+async fn run_auth_command(
+    mut config_repository: ConfigRepository,
+    command: AuthCommand,
+    network: &NetworkArgs,
+) -> Result<(), Error> {
+    let profile = config_repository.get_profile().clone();
+    config_repository.require_tokens()?;
+
+    match command {
+        AuthCommand::Status => {
+            println!("Profile: {}", profile.name);
+            println!("Store:   {}", config_repository.get_credential_store_name());
+            println!("Scope:   {}", config_repository.get_saved_scope());
+            println!(
+                "Expires: {}",
+                describe_expiry(config_repository.get_saved_expires_in())
+            );
+
+            let client = network.client(&config_repository)?;
+            match client.user_info().await {
+                Ok(user) => match user.user_email {
+                    Some(email) => println!(
+                        "Account: {} <{}> (id {})",
+                        user.user_name, email, user.user_id
+                    ),
+                    None => println!("Account: {} (id {})", user.user_name, user.user_id),
+                },
+                Err(e) => {
+                    println!("Account: unavailable");
+                    return Err(e);
+                }
+            }
+        }
+        AuthCommand::Refresh => {
+            let (client_id, client_secret) = client_credentials(&profile)?;
+            let config = TokenService::new(
+                &client_id,
+                &client_secret,
+                &config_repository.get_saved_refresh_token(),
+                config_repository.get_config_path(),
+                &profile.name,
+            )
+            .refreshing_token()
+            .await?;
+
+            println!("Refreshed. Expires: {}", describe_expiry(config.expires_in));
+        }
+        AuthCommand::Logout => {
+            config_repository.delete_tokens()?;
+            println!("Deleted the saved tokens of profile '{}'.", profile.name);
+            // Inoreader の API にはトークンを失効させるエンドポイントがない
+            println!(
+                "Inoreader has no token revocation API. To revoke the grant itself, remove this app from the connected apps in your Inoreader account settings."
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn run_api_command(
+    config_repository: &ConfigRepository,
+    command: ApiCommand,
+    network: &NetworkArgs,
+) -> Result<(), Error> {
+    config_repository.require_tokens()?;
+    let client = network.client(config_repository)?;
+
+    match command {
+        ApiCommand::Quota => {
+            // 使用量はレスポンスヘッダーでしか分からないので軽いリクエストを 1 回送る
+            client.user_info().await?;
+            let quota = match client.quota() {
+                Some(quota) => quota,
+                None => {
+                    println!("The API did not report the usage.");
+                    return Ok(());
+                }
+            };
+            for (name, zone) in [
+                ("Zone 1 (read): ", quota.zone1),
+                ("Zone 2 (write):", quota.zone2),
+            ] {
+                match zone {
+                    Some(zone) => println!("{} {} / {} requests", name, zone.usage, zone.limit),
+                    None => println!("{} unknown", name),
+                }
+            }
+            if let Some(reset_after) = quota.reset_after {
+                println!("Resets in:      {}", describe_wait(reset_after));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Formats a Unix time in local time.
+fn describe_time(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(time) => time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M %:z")
+            .to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Formats a saved expiry time (Unix seconds) in local time, relative to now.
+fn describe_expiry(expires_at: u64) -> String {
+    let expires = match DateTime::from_timestamp(expires_at as i64, 0) {
+        Some(expires) => expires.with_timezone(&Local),
+        None => return format!("unknown ({})", expires_at),
+    };
+
+    let remaining = expires.signed_duration_since(Local::now());
+    let relative = if remaining.num_seconds() > 0 {
+        format!("in {} min", remaining.num_minutes())
+    } else {
+        format!(
+            "expired {} min ago, refreshed on the next request",
+            -remaining.num_minutes()
+        )
+    };
+    format!("{} ({})", expires.format("%Y-%m-%d %H:%M:%S %:z"), relative)
+}
+
+/// Client credentials of `profile`, falling back to the `INOREADER_*` environment variables.
+fn client_credentials(profile: &Profile) -> Result<(String, String), ConfigError> {
+    let client_id = profile_setting(&profile.client_id, "client_id", "INOREADER_CLIENT_ID")?;
+    let client_secret = profile_setting(
+        &profile.client_secret,
+        "client_secret",
+        "INOREADER_CLIENT_SECRET",
+    )?;
+    Ok((client_id, client_secret))
+}
+
+fn profile_setting(value: &Option<String>, field: &str, env: &str) -> Result<String, ConfigError> {
+    value
+        .clone()
+        .or_else(|| env::var(env).ok())
+        .ok_or_else(|| ConfigError::Missing {
+            field: field.to_string(),
+            env: env.to_string(),
+        })
+}
+
+async fn authenticate(
+    config_repository: &ConfigRepository,
+    auth_args: &AuthArgs,
+) -> Result<(), Error> {
+    let profile = config_repository.get_profile();
+    let (client_id, client_secret) = client_credentials(profile)?;
+    let redirect_uri = profile_setting(
+        &profile.redirect_uri,
+        "redirect_uri",
+        "INOREADER_REDIRECT_URI",
+    )?;
+
+    let use_pkce = auth_args.pkce || env_flag("INOREADER_USE_PKCE");
+    let scope = match (auth_args.scope, env::var("INOREADER_SCOPE")) {
+        (Some(scope), _) => scope,
+        (None, Ok(scope)) => scope
+            .parse::<Scope>()
+            .map_err(|e| ConfigError::InvalidField {
+                field: "INOREADER_SCOPE".to_string(),
+                message: e.to_string(),
+            })?,
+        (None, Err(_)) => Scope::Read,
+    };
+
+    let config_path = config_repository.get_config_path();
+    AuthenticationService::new(
+        &client_id,
+        &client_secret,
+        &redirect_uri,
+        config_path,
+        &profile.name,
+    )
+    .with_scope(scope)
+    .with_manual_entry(auth_args.manual)
+    .with_pkce(use_pkce)
+    .app_authenticate()
+    .await?;
+
+    let config_repository = ConfigRepository::new(config_path, &profile.name)?;
+    println!(
+        "Authorized with scope: {}",
+        config_repository.get_saved_scope().as_str()
+    );
+    Ok(())
 }
 
-fn exist_token_file() -> bool {
-    let token_path = Path::new(".config");
-    token_path.exists()
+fn env_flag(name: &str) -> bool {
+    env::var(name)
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
 }
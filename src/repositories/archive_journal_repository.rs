@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::domain::OutputFormat;
+use crate::repositories::json_lines_repository::JsonLinesRepository;
+
+/// One line of the journal, appended as each step of `archive` is done.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JournalEntry {
+    Started {
+        output_path: String,
+        output_format: OutputFormat,
+    },
+    /// The items are in the archive file and on disk
+    Archived {
+        ids: Vec<String>,
+    },
+    Unstarred {
+        ids: Vec<String>,
+    },
+}
+
+/// State of an `archive` run that has not finished, rebuilt from its journal.
+#[derive(Debug, PartialEq)]
+pub struct ArchiveJournal {
+    pub output_path: String,
+    pub output_format: OutputFormat,
+    /// Archived items, in the order they were written
+    pub archived: Vec<String>,
+    pub unstarred: HashSet<String>,
+}
+
+impl ArchiveJournal {
+    /// Items that are archived but still starred.
+    pub fn pending(&self) -> Vec<String> {
+        self.archived
+            .iter()
+            .filter(|id| !self.unstarred.contains(*id))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Append-only journal of the `archive` run of a profile, kept next to the config file.
+/// Every entry is flushed to disk before the next step, so an interrupted run can resume
+/// without unstarring anything that is not in the archive.
+pub struct ArchiveJournalRepository {
+    journal: JsonLinesRepository,
+}
+
+impl ArchiveJournalRepository {
+    pub fn new(config_path: &str, profile: &str) -> Self {
+        ArchiveJournalRepository {
+            journal: JsonLinesRepository::new(
+                Path::new(config_path).with_file_name(format!("archive_journal_{}.jsonl", profile)),
+            ),
+        }
+    }
+
+    /// Reads the journal of an unfinished run.
+    pub fn load(&self) -> io::Result<Option<ArchiveJournal>> {
+        let mut journal: Option<ArchiveJournal> = None;
+        for entry in self.journal.load()? {
+            match (entry, &mut journal) {
+                (
+                    JournalEntry::Started {
+                        output_path,
+                        output_format,
+                    },
+                    None,
+                ) => {
+                    journal = Some(ArchiveJournal {
+                        output_path,
+                        output_format,
+                        archived: Vec::new(),
+                        unstarred: HashSet::new(),
+                    })
+                }
+                (JournalEntry::Archived { ids }, Some(journal)) => journal.archived.extend(ids),
+                (JournalEntry::Unstarred { ids }, Some(journal)) => journal.unstarred.extend(ids),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{} is not a valid archive journal",
+                            self.journal.path().display()
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(journal)
+    }
+
+    /// Starts the journal of a new run, replacing any old one.
+    pub fn start(&self, output_path: &str, output_format: OutputFormat) -> io::Result<()> {
+        self.journal.clear()?;
+        self.journal.append(&[JournalEntry::Started {
+            output_path: output_path.to_string(),
+            output_format,
+        }])
+    }
+
+    pub fn record_archived(&self, ids: &[String]) -> io::Result<()> {
+        self.journal
+            .append(&[JournalEntry::Archived { ids: ids.to_vec() }])
+    }
+
+    pub fn record_unstarred(&self, ids: &[String]) -> io::Result<()> {
+        self.journal
+            .append(&[JournalEntry::Unstarred { ids: ids.to_vec() }])
+    }
+
+    /// Deletes the journal once the run has finished.
+    pub fn clear(&self) -> io::Result<()> {
+        self.journal.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_journal_tracks_pending_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config");
+        let repository = ArchiveJournalRepository::new(config_path.to_str().unwrap(), "default");
+
+        assert_eq!(repository.load().unwrap(), None);
+        repository.start("archive.csv", OutputFormat::Csv).unwrap();
+        repository.record_archived(&ids(&["a", "b", "c"])).unwrap();
+        repository.record_unstarred(&ids(&["a"])).unwrap();
+        repository.record_archived(&ids(&["d"])).unwrap();
+
+        let journal = repository.load().unwrap().unwrap();
+        assert_eq!(journal.output_path, "archive.csv");
+        assert_eq!(journal.output_format, OutputFormat::Csv);
+        assert_eq!(journal.pending(), ids(&["b", "c", "d"]));
+
+        repository.clear().unwrap();
+        assert_eq!(repository.load().unwrap(), None);
+    }
+}
@@ -1,25 +1,363 @@
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use chrono::{DateTime, SecondsFormat};
+
+use crate::domain::Item;
+use crate::repositories::json_lines_repository::JsonLinesRepository;
+
+const CSV_HEADER: &str = "title,url,published,feed,labels,author,id\n";
 
 pub struct ArticlesDataRepository;
 
 impl ArticlesDataRepository {
-    #[allow(dead_code)]
-    pub fn save_articles_to_json(articles: &Vec<(String, String)>) -> Result<(), std::io::Error> {
-        let json = serde_json::to_string(articles)?;
-        let mut file = File::create("articles.json")?;
+    /// Writes `articles` as a JSON array of items. With `append`, they are added to the existing array.
+    /// The file is replaced atomically and is on disk when this returns. Appending reads and
+    /// rewrites the whole file, so files that grow page by page use JSON Lines instead.
+    pub fn save_articles_to_json(
+        articles: &[Item],
+        path: &str,
+        append: bool,
+    ) -> Result<(), std::io::Error> {
+        let mut all_articles: Vec<Item> = Vec::new();
+        if append {
+            if let Ok(contents) = fs::read_to_string(path) {
+                all_articles = serde_json::from_str(&contents)?;
+            }
+        }
+        all_articles.extend(articles.iter().cloned());
+
+        let json = serde_json::to_string(&all_articles)?;
+        // 書き込み途中で落ちても既存のファイルが壊れないよう、別名で書いてから置き換える
+        let temp_path = format!("{}.tmp", path);
+        let mut file = File::create(&temp_path)?;
         file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Self::sync_parent_dir(path)
+    }
+
+    /// Appends `articles` to a JSON Lines file, one item per line, as `archive` does page by
+    /// page. The items are on disk when this returns.
+    pub fn append_articles_to_json_lines(articles: &[Item], path: &str) -> Result<(), io::Error> {
+        let is_new = !fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0);
+        if !is_new && Self::is_json_array(path)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is a JSON array; archives are written as JSON Lines, so choose another file",
+                    path
+                ),
+            ));
+        }
+        JsonLinesRepository::new(path).append(articles)?;
+        if is_new {
+            Self::sync_parent_dir(path)?;
+        }
+        Ok(())
+    }
+
+    fn is_json_array(path: &str) -> Result<bool, io::Error> {
+        let mut head = Vec::new();
+        File::open(path)?.take(256).read_to_end(&mut head)?;
+        Ok(head.trim_ascii_start().starts_with(b"["))
+    }
+
+    /// Writes `articles` as CSV. With `append`, rows are added to the end of an existing file.
+    /// The rows are on disk when this returns.
+    pub fn save_articles_to_csv(
+        articles: &[Item],
+        path: &str,
+        append: bool,
+    ) -> Result<(), std::io::Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        if append {
+            Self::drop_torn_row(&mut file)?;
+        }
+        let has_rows = file.metadata()?.len() > 0;
+        if !has_rows {
+            file.write_all(CSV_HEADER.as_bytes())?;
+        }
+
+        for item in articles {
+            let published = DateTime::from_timestamp(item.published as i64, 0)
+                .filter(|_| item.published > 0)
+                .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+                .unwrap_or_default();
+            let row = [
+                item.title.as_str(),
+                item.url().unwrap_or_default(),
+                &published,
+                item.feed_title().unwrap_or_default(),
+                &item.labels().join(";"),
+                item.author.as_deref().unwrap_or_default(),
+                item.id.as_str(),
+            ]
+            .map(Self::csv_field)
+            .join(",");
+            file.write_all(format!("{}\n", row).as_bytes())?;
+        }
+        file.sync_all()?;
+        if !has_rows {
+            Self::sync_parent_dir(path)?;
+        }
         Ok(())
     }
 
-    pub fn save_articles_to_csv(articles: &Vec<(String, String)>) -> Result<(), std::io::Error> {
-        let mut file = File::create("articles.csv")?;
-        file.write_all("title,url\n".as_bytes())?;
+    /// Cuts off a last row left incomplete by a crash, so that appended rows do not continue it.
+    /// Every row ends with its quoted id, which has no quotes in it, so a complete file ends
+    /// with `"\n` not preceded by another `"` (an escaped quote), or is the header.
+    fn drop_torn_row(file: &mut File) -> Result<(), std::io::Error> {
+        let len = file.metadata()?.len();
+        if len == 0 {
+            return Ok(());
+        }
+        let mut tail = Vec::with_capacity(3);
+        file.seek(SeekFrom::Start(len.saturating_sub(3)))?;
+        file.read_to_end(&mut tail)?;
+        if tail.ends_with(b"\"\n") && !tail.ends_with(b"\"\"\n") {
+            return Ok(());
+        }
+
+        let mut contents = Vec::with_capacity(len as usize);
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut contents)?;
+        if contents == CSV_HEADER.as_bytes() {
+            return Ok(());
+        }
+        // 引用符の外にある最後の改行までが完全な行
+        let mut quoted = false;
+        let mut complete = 0;
+        for (i, &byte) in contents.iter().enumerate() {
+            match byte {
+                b'"' => quoted = !quoted,
+                b'\n' if !quoted => complete = i + 1,
+                _ => {}
+            }
+        }
+        file.set_len(complete as u64)
+    }
 
-        for (title, url) in articles {
-            let escaped_title = title.replace("\"", "\"\"");
-            file.write_all(format!("\"{}\",\"{}\"\n", escaped_title, url).as_bytes())?;
+    /// Makes a new or renamed file in the directory durable.
+    fn sync_parent_dir(path: &str) -> Result<(), std::io::Error> {
+        // Windows ではディレクトリを開いて同期できない
+        if cfg!(unix) {
+            let dir = Path::new(path)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            File::open(dir)?.sync_all()?;
         }
         Ok(())
     }
+
+    fn csv_field(value: &str) -> String {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    }
+
+    /// Reads the item IDs of an earlier export or archive, JSON, JSON Lines or CSV (told apart
+    /// by the content).
+    pub fn load_item_ids(path: &str) -> Result<Vec<String>, io::Error> {
+        let contents = fs::read_to_string(path)?;
+        if contents.trim_start().starts_with('[') {
+            let items: Vec<Item> = serde_json::from_str(&contents)?;
+            return Ok(items.into_iter().map(|item| item.id).collect());
+        }
+        if contents.trim_start().starts_with('{') {
+            let items: Vec<Item> = JsonLinesRepository::new(path).load()?;
+            return Ok(items.into_iter().map(|item| item.id).collect());
+        }
+
+        let mut rows = Self::parse_csv(&contents).into_iter();
+        let header = rows.next().unwrap_or_default();
+        let id_column = header.iter().position(|name| name == "id").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has no id column; export it again first", path),
+            )
+        })?;
+        Ok(rows
+            .filter_map(|mut row| (id_column < row.len()).then(|| row.swap_remove(id_column)))
+            .filter(|id| !id.is_empty())
+            .collect())
+    }
+
+    /// Splits CSV as written by `save_articles_to_csv`: quoted fields may contain commas,
+    /// doubled quotes and line breaks.
+    fn parse_csv(contents: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = contents.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, quoted) {
+                ('"', true) if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                ('"', _) => quoted = !quoted,
+                (',', false) => row.push(std::mem::take(&mut field)),
+                ('\n', false) => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                ('\r', false) => {}
+                (c, _) => field.push(c),
+            }
+        }
+        if !field.is_empty() || !row.is_empty() {
+            row.push(field);
+            rows.push(row);
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(title: &str, url: &str) -> Item {
+        serde_json::from_value(serde_json::json!({
+            "id": "tag:google.com,2005:reader/item/1",
+            "title": title,
+            "published": 1617969599,
+            "categories": ["user/1/label/Tech", "user/1/label/News"],
+            "canonical": [{ "href": url }],
+            "origin": { "streamId": "feed/https://a/rss", "title": "Feed \"A\"" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_append_after_a_torn_csv_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.csv");
+        let path = path.to_str().unwrap();
+        let mut second = item("Second", "https://b");
+        second.id = "tag:google.com,2005:reader/item/2".to_string();
+
+        for torn in [
+            "\"Torn",
+            "\"Torn\nafter a newline\n",
+            "\"Torn \"\"quote\"\"\n",
+        ] {
+            ArticlesDataRepository::save_articles_to_csv(
+                &[item("First\nrow", "https://a")],
+                path,
+                false,
+            )
+            .unwrap();
+            let written = fs::read_to_string(path).unwrap();
+            OpenOptions::new()
+                .append(true)
+                .open(path)
+                .unwrap()
+                .write_all(torn.as_bytes())
+                .unwrap();
+
+            ArticlesDataRepository::save_articles_to_csv(std::slice::from_ref(&second), path, true)
+                .unwrap();
+            let contents = fs::read_to_string(path).unwrap();
+            assert!(contents.starts_with(&written), "{:?}", torn);
+            assert!(!contents.contains("Torn"), "{:?}", torn);
+            assert_eq!(
+                ArticlesDataRepository::load_item_ids(path).unwrap(),
+                vec![
+                    "tag:google.com,2005:reader/item/1".to_string(),
+                    second.id.clone()
+                ]
+            );
+        }
+
+        // ヘッダーだけのファイルには行を足すだけ
+        ArticlesDataRepository::save_articles_to_csv(&[], path, false).unwrap();
+        ArticlesDataRepository::save_articles_to_csv(std::slice::from_ref(&second), path, true)
+            .unwrap();
+        assert_eq!(
+            ArticlesDataRepository::load_item_ids(path).unwrap(),
+            vec![second.id]
+        );
+    }
+
+    #[test]
+    fn test_append_articles() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv_path = dir.path().join("articles.csv");
+        let json_path = dir.path().join("articles.json");
+        let (csv_path, json_path) = (csv_path.to_str().unwrap(), json_path.to_str().unwrap());
+        let first = vec![item("A \"quoted\" title", "https://a")];
+        let second = vec![item("B", "https://b")];
+
+        ArticlesDataRepository::save_articles_to_csv(&first, csv_path, false).unwrap();
+        ArticlesDataRepository::save_articles_to_csv(&second, csv_path, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(csv_path).unwrap(),
+            format!(
+                "{}{}{}",
+                CSV_HEADER,
+                "\"A \"\"quoted\"\" title\",\"https://a\",\"2021-04-09T11:59:59Z\",\"Feed \"\"A\"\"\",\"Tech;News\",\"\",\"tag:google.com,2005:reader/item/1\"\n",
+                "\"B\",\"https://b\",\"2021-04-09T11:59:59Z\",\"Feed \"\"A\"\"\",\"Tech;News\",\"\",\"tag:google.com,2005:reader/item/1\"\n"
+            )
+        );
+
+        ArticlesDataRepository::save_articles_to_json(&first, json_path, false).unwrap();
+        ArticlesDataRepository::save_articles_to_json(&second, json_path, true).unwrap();
+        let saved: Vec<Item> =
+            serde_json::from_str(&fs::read_to_string(json_path).unwrap()).unwrap();
+        assert_eq!(saved, [first, second].concat());
+    }
+
+    #[test]
+    fn test_load_item_ids_from_csv_and_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv_path = dir.path().join("articles.csv");
+        let json_path = dir.path().join("articles.json");
+        let (csv_path, json_path) = (csv_path.to_str().unwrap(), json_path.to_str().unwrap());
+        let mut multiline = item("Line one\nline \"two\", end", "https://b");
+        multiline.id = "tag:google.com,2005:reader/item/2".to_string();
+        let articles = vec![item("A", "https://a"), multiline];
+        let ids = vec![
+            "tag:google.com,2005:reader/item/1".to_string(),
+            "tag:google.com,2005:reader/item/2".to_string(),
+        ];
+
+        ArticlesDataRepository::save_articles_to_csv(&articles, csv_path, false).unwrap();
+        assert_eq!(
+            ArticlesDataRepository::load_item_ids(csv_path).unwrap(),
+            ids
+        );
+
+        ArticlesDataRepository::save_articles_to_json(&articles, json_path, false).unwrap();
+        assert_eq!(
+            ArticlesDataRepository::load_item_ids(json_path).unwrap(),
+            ids
+        );
+
+        let json_lines_path = dir.path().join("archive.jsonl");
+        let json_lines_path = json_lines_path.to_str().unwrap();
+        ArticlesDataRepository::append_articles_to_json_lines(&articles[..1], json_lines_path)
+            .unwrap();
+        ArticlesDataRepository::append_articles_to_json_lines(&articles[1..], json_lines_path)
+            .unwrap();
+        assert_eq!(
+            ArticlesDataRepository::load_item_ids(json_lines_path).unwrap(),
+            ids
+        );
+        let e = ArticlesDataRepository::append_articles_to_json_lines(&articles, json_path)
+            .unwrap_err();
+        assert!(e.to_string().contains("is a JSON array"));
+
+        fs::write(csv_path, "title,url\n\"A\",\"https://a\"\n").unwrap();
+        let e = ArticlesDataRepository::load_item_ids(csv_path).unwrap_err();
+        assert!(e.to_string().contains("no id column"));
+    }
 }
@@ -1,98 +1,674 @@
-use crate::domain::Config;
-use crate::CONFIG_FILE_NAME;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::Path;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
+use crate::domain::{Config, CredentialStoreKind, OutputFormat, Profile, Scope};
+use crate::error::Result;
+use crate::repositories::credential_store_repository::{
+    migrate_credentials, remove_if_exists, write_private_file, CredentialStore,
+};
+use crate::services::authentication_service::AuthenticationError;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Token file written to the working directory by older versions.
+const LEGACY_CONFIG_FILE_NAME: &str = ".config";
+const CONFIG_DIR_NAME: &str = "inoreader";
+const CONFIG_FILE_NAME: &str = "config";
+pub const DEFAULT_PROFILE: &str = "default";
+/// Version of the config file schema written by this build.
+pub const CONFIG_VERSION: u32 = 1;
+/// Keys of the `Key:Value` format that belong to the tokens of a profile.
+const LEGACY_TOKEN_KEYS: [&str; 6] = [
+    "AuthorizationCode",
+    "State",
+    "AccessToken",
+    "RefreshToken",
+    "ExpiresIn",
+    "Scope",
+];
+
+/// A config file or saved token set that could not be read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    Syntax(String),
+    InvalidField {
+        field: String,
+        message: String,
+    },
+    /// A required setting is neither in the profile nor in the environment
+    Missing {
+        field: String,
+        env: String,
+    },
+    UnknownProfile(String),
+}
+
+impl ConfigError {
+    fn invalid_field(field: &str, message: &str) -> Self {
+        ConfigError::InvalidField {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Syntax(e) => write!(f, "invalid TOML: {}", e),
+            ConfigError::InvalidField { field, message } => {
+                write!(f, "invalid value for `{}`: {}", field, message)
+            }
+            ConfigError::Missing { field, env } => write!(
+                f,
+                "`{}` is not set; add it to the profile or set {}",
+                field, env
+            ),
+            ConfigError::UnknownProfile(name) => write!(f, "profile '{}' does not exist", name),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for io::Error {
+    fn from(e: ConfigError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// The config file.
+///
+/// ```toml
+/// version = 1
+/// default_profile = "personal"
+///
+/// [profiles.personal]
+/// client_id = "..."
+/// credential_store = "file"
+///
+/// [profiles.personal.tokens]
+/// refresh_token = "..."
+/// ```
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    #[serde(default = "ConfigFile::current_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ProfileSection>,
+}
+
+/// Settings of one profile. Unset values fall back to the defaults of `Profile`.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ProfileSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_store: Option<CredentialStoreKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_path: Option<String>,
+    /// Only used by the `file` credential store
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<Config>,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile {
+            version: CONFIG_VERSION,
+            default_profile: None,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConfigFile {
+    fn current_version() -> u32 {
+        CONFIG_VERSION
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if !path.is_file() {
+            return Ok(ConfigFile::default());
+        }
+        Ok(Self::parse(&fs::read_to_string(path)?)?)
+    }
+
+    /// Parses a TOML config file, or a `Key:Value` file written by older versions.
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let table = if is_legacy_format(contents) {
+            legacy_config_table(contents)
+        } else {
+            parse_table(contents)?
+        };
+
+        let config_file: ConfigFile = deserialize(table)?;
+        if config_file.version == 0 || config_file.version > CONFIG_VERSION {
+            return Err(ConfigError::invalid_field(
+                "version",
+                &format!(
+                    "unsupported config version {} (this build reads up to {})",
+                    config_file.version, CONFIG_VERSION
+                ),
+            ));
+        }
+        for (name, section) in &config_file.profiles {
+            if let Some(tokens) = &section.tokens {
+                validate_tokens(tokens, &format!("profiles.{}.tokens.", name))?;
+            }
+        }
+        Ok(config_file)
+    }
+
+    /// Writes the file with owner-only permissions, or removes it when nothing is left.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        self.profiles.retain(|_, section| !section.is_empty());
+        if self.default_profile.is_none() && self.profiles.is_empty() {
+            return remove_if_exists(path);
+        }
+
+        let contents = toml::to_string(self).map_err(io::Error::other)?;
+        write_private_file(path, contents.as_bytes())
+    }
+
+    fn profile(&self, name: &str) -> Result<Profile, ConfigError> {
+        match self.profiles.get(name) {
+            Some(section) => section.to_profile(name),
+            None => Ok(Profile::new(name, CredentialStoreKind::from_env()?)),
+        }
+    }
+}
+
+impl ProfileSection {
+    fn is_empty(&self) -> bool {
+        *self == ProfileSection::default()
+    }
+
+    fn to_profile(&self, name: &str) -> Result<Profile, ConfigError> {
+        let credential_store = match (self.credential_store, &self.tokens) {
+            (Some(kind), _) => kind,
+            (None, Some(_)) => CredentialStoreKind::File,
+            (None, None) => CredentialStoreKind::from_env()?,
+        };
+        let mut profile = Profile::new(name, credential_store);
+        profile.client_id = self.client_id.clone();
+        profile.client_secret = self.client_secret.clone();
+        profile.redirect_uri = self.redirect_uri.clone();
+        if let Some(format) = self.output_format {
+            profile.output_format = format;
+        }
+        profile.output_path = self.output_path.clone();
+        Ok(profile)
+    }
+}
+
+/// Reads tokens kept outside the config file: TOML, or `Key:Value` lines from older versions.
+pub(crate) fn parse_tokens(contents: &str) -> Result<Config, ConfigError> {
+    let table = if is_legacy_format(contents) {
+        let mut table = toml::Table::new();
+        for (key, value) in legacy_entries(contents) {
+            insert_legacy_entry(&mut table, key, value);
+        }
+        match table.remove("tokens") {
+            Some(toml::Value::Table(tokens)) => tokens,
+            _ => toml::Table::new(),
+        }
+    } else {
+        parse_table(contents)?
+    };
+
+    let tokens: Config = deserialize(table)?;
+    validate_tokens(&tokens, "")?;
+    Ok(tokens)
+}
+
+pub(crate) fn serialize_tokens(tokens: &Config) -> io::Result<String> {
+    toml::to_string(tokens).map_err(io::Error::other)
+}
+
+fn parse_table(contents: &str) -> Result<toml::Table, ConfigError> {
+    contents
+        .parse::<toml::Table>()
+        .map_err(|e| ConfigError::Syntax(e.to_string().trim_end().to_string()))
+}
+
+// serde_path_to_error で失敗したフィールドのパス (profiles.x.tokens.expires_in など) を得る
+fn deserialize<T: DeserializeOwned>(table: toml::Table) -> Result<T, ConfigError> {
+    serde_path_to_error::deserialize(toml::Value::Table(table)).map_err(|e| {
+        ConfigError::InvalidField {
+            field: e.path().to_string(),
+            message: e.inner().message().to_string(),
+        }
+    })
+}
+
+fn validate_tokens(tokens: &Config, prefix: &str) -> Result<(), ConfigError> {
+    if tokens.refresh_token.is_empty() {
+        return Err(ConfigError::invalid_field(
+            &format!("{}refresh_token", prefix),
+            "must not be empty",
+        ));
+    }
+    Ok(())
+}
+
+fn legacy_section_name(line: &str) -> Option<&str> {
+    line.strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+}
+
+fn legacy_entries(contents: &str) -> impl Iterator<Item = (&str, &str)> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().split_once(':'))
+}
+
+/// `Key:Value` lines, optionally grouped in `[profile]` sections. Not valid TOML.
+fn is_legacy_format(contents: &str) -> bool {
+    let lines: Vec<&str> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let is_entry = |line: &str| {
+        line.split_once(':').is_some_and(|(key, _)| {
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+    };
+
+    lines.iter().any(|line| is_entry(line))
+        && lines
+            .iter()
+            .all(|line| is_entry(line) || legacy_section_name(line).is_some())
+}
+
+/// Converts a `Key:Value` file to the table layout of `ConfigFile`.
+fn legacy_config_table(contents: &str) -> toml::Table {
+    let mut top = toml::Table::new();
+    let mut profiles = toml::Table::new();
+    let mut current: Option<String> = None;
+
+    for line in contents.lines().map(str::trim) {
+        if let Some(name) = legacy_section_name(line) {
+            profiles
+                .entry(name)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            current = Some(name.to_string());
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let table = match &current {
+                Some(name) => profiles
+                    .get_mut(name.as_str())
+                    .and_then(toml::Value::as_table_mut)
+                    .unwrap(),
+                None => &mut top,
+            };
+            insert_legacy_entry(table, key, value);
+        }
+    }
+
+    // プロファイル導入前のファイルはトークンがトップレベルにあるので default に移す
+    if let Some(tokens) = top.remove("tokens") {
+        profiles
+            .entry(DEFAULT_PROFILE)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .unwrap()
+            .insert("tokens".to_string(), tokens);
+    }
+    if !profiles.is_empty() {
+        top.insert("profiles".to_string(), toml::Value::Table(profiles));
+    }
+    top
+}
+
+fn insert_legacy_entry(table: &mut toml::Table, key: &str, value: &str) {
+    let field = snake_case(key);
+    // ExpiresIn は数値にできなければ文字列のまま残し、検証でフィールド名付きのエラーにする
+    let value = match value.parse::<i64>() {
+        Ok(number) if key == "ExpiresIn" => toml::Value::Integer(number),
+        _ => toml::Value::String(value.to_string()),
+    };
+
+    if LEGACY_TOKEN_KEYS.contains(&key) {
+        table
+            .entry("tokens")
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .unwrap()
+            .insert(field, value);
+    } else {
+        table.insert(field, value);
+    }
+}
+
+fn snake_case(key: &str) -> String {
+    let mut field = String::new();
+    for (i, c) in key.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            field.push('_');
+        }
+        field.push(c.to_ascii_lowercase());
+    }
+    field
+}
 
 pub struct ConfigRepository {
     config_path: String,
-    access_token: String,
-    state: String,
-    refresh_token: String,
-    expires_in: u64,
+    profile: Profile,
+    store: Box<dyn CredentialStore>,
+    tokens: Option<Config>,
 }
 
 impl ConfigRepository {
-    pub fn new(config_path: &str) -> Self {
-        let config = Self::load_config(CONFIG_FILE_NAME);
-
-        if config.is_empty() {
-            return ConfigRepository {
-                config_path: config_path.to_string(),
-                access_token: "".to_string(),
-                state: "".to_string(),
-                refresh_token: "".to_string(),
-                expires_in: 0,
-            };
+    /// Loads `profile` from `config_path` together with the tokens and the client secret from
+    /// its credential store.
+    pub fn new(config_path: &str, profile: &str) -> Result<Self> {
+        let mut profile = ConfigFile::load(Path::new(config_path))?.profile(profile)?;
+        let store = profile.credential_store.open(config_path, &profile.name);
+        let tokens = store.load()?;
+        if let Some(client_secret) = store.load_client_secret()? {
+            profile.client_secret = Some(client_secret);
         }
 
-        ConfigRepository {
+        Ok(ConfigRepository {
             config_path: config_path.to_string(),
-            access_token: config.get("AccessToken").unwrap().to_string(),
-            state: config.get("State").unwrap().to_string(),
-            refresh_token: config.get("RefreshToken").unwrap().to_string(),
-            expires_in: config.get("ExpiresIn").unwrap().parse::<u64>().unwrap(),
-        }
-    }
-
-    fn load_config(config_path: &str) -> HashMap<String, String> {
-        let path: &Path = Path::new(config_path);
-        let mut settings = HashMap::new();
-        if path.exists() {
-            let mut file = File::open(path).expect("Failed to open token file");
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)
-                .expect("Failed to read token file");
-
-            for line in contents.lines() {
-                let parts: Vec<&str> = line.splitn(2, ':').collect();
-                if parts.len() == 2 {
-                    settings.insert(parts[0].to_string(), parts[1].to_string());
-                }
-            }
+            profile,
+            store,
+            tokens,
+        })
+    }
+
+    /// Whether `profile` has saved tokens. Unlike `new`, this does not ask for the passphrase
+    /// of an encrypted store.
+    pub fn profile_has_saved_tokens(config_path: &str, profile: &str) -> Result<bool> {
+        let profile = ConfigFile::load(Path::new(config_path))?.profile(profile)?;
+        Ok(profile
+            .credential_store
+            .open(config_path, &profile.name)
+            .exists()?)
+    }
+
+    /// Whether `profile` has saved tokens or a client secret in its credential store.
+    pub fn profile_has_saved_credentials(config_path: &str, profile: &str) -> Result<bool> {
+        let profile = ConfigFile::load(Path::new(config_path))?.profile(profile)?;
+        let store = profile.credential_store.open(config_path, &profile.name);
+        Ok(store.exists()? || store.load_client_secret()?.is_some())
+    }
+
+    /// Picks the profile: `--profile`, then `INOREADER_PROFILE`, then the saved default.
+    pub fn resolve_profile_name(config_path: &str, cli_profile: Option<&str>) -> String {
+        if let Some(name) = cli_profile {
+            return name.to_string();
+        }
+        if let Ok(name) = env::var("INOREADER_PROFILE") {
+            return name;
         }
-        settings
+        ConfigFile::load(Path::new(config_path))
+            .ok()
+            .and_then(|config_file| config_file.default_profile)
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    /// Returns all saved profiles and the name of the default one.
+    pub fn list_profiles(config_path: &str) -> Result<(Vec<Profile>, String)> {
+        let config_file = ConfigFile::load(Path::new(config_path))?;
+        let profiles = config_file
+            .profiles
+            .iter()
+            .filter(|(_, section)| !section.is_empty())
+            .map(|(name, section)| section.to_profile(name))
+            .collect::<Result<_, _>>()?;
+        let default = config_file
+            .default_profile
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        Ok((profiles, default))
     }
 
-    pub fn save_config(&self, config: &Config) -> io::Result<()> {
-        // ファイルに設定を保存するロジック
-        let config_file_path = Path::new(&self.config_path);
-        let mut file = File::create(config_file_path)?;
-        writeln!(file, "AuthorizationCode:{}", config.authorization_code)?;
-        writeln!(file, "State:{}", config.state)?;
-        writeln!(file, "AccessToken:{}", config.access_token)?;
-        writeln!(file, "RefreshToken:{}", config.refresh_token)?;
-        writeln!(file, "ExpiresIn:{}", config.expires_in)?;
+    /// Saves the settings of `profile`, keeping any tokens already saved. The client secret
+    /// goes to the credential store of the profile; when it is unset, the saved one is kept.
+    pub fn save_profile(config_path: &str, profile: &Profile) -> Result<()> {
+        if let Some(client_secret) = &profile.client_secret {
+            profile
+                .credential_store
+                .open(config_path, &profile.name)
+                .save_client_secret(client_secret)?;
+        }
+
+        let path = Path::new(config_path);
+        let mut config_file = ConfigFile::load(path)?;
+        let section = config_file
+            .profiles
+            .entry(profile.name.clone())
+            .or_default();
+
+        section.client_id = profile.client_id.clone();
+        if profile.credential_store != CredentialStoreKind::File {
+            // 以前のバージョンは保存先によらず平文で書いていた
+            section.client_secret = None;
+        }
+        section.redirect_uri = profile.redirect_uri.clone();
+        section.credential_store = Some(profile.credential_store);
+        section.output_format = Some(profile.output_format);
+        section.output_path = profile.output_path.clone();
+
+        Ok(config_file.save(path)?)
+    }
+
+    pub fn set_default_profile(config_path: &str, name: &str) -> Result<()> {
+        let path = Path::new(config_path);
+        let mut config_file = ConfigFile::load(path)?;
+        if !config_file.profiles.contains_key(name) {
+            return Err(ConfigError::UnknownProfile(name.to_string()).into());
+        }
+        config_file.default_profile = Some(name.to_string());
+        Ok(config_file.save(path)?)
+    }
+
+    /// Deletes the tokens and settings of `name`. Returns `false` if it did not exist.
+    pub fn remove_profile(config_path: &str, name: &str) -> Result<bool> {
+        let path = Path::new(config_path);
+        let profile = match ConfigFile::load(path)?.profiles.get(name) {
+            Some(section) => section.to_profile(name)?,
+            None => return Ok(false),
+        };
+        let store = profile.credential_store.open(config_path, name);
+        store.delete()?;
+        store.delete_client_secret()?;
+
+        let mut config_file = ConfigFile::load(path)?;
+        config_file.profiles.remove(name);
+        if config_file.default_profile.as_deref() == Some(name) {
+            config_file.default_profile = None;
+        }
+        config_file.save(path)?;
+        Ok(true)
+    }
+
+    /// Moves this profile's tokens to another credential store and records the new store.
+    pub fn migrate_credentials(&mut self, to: CredentialStoreKind) -> Result<bool> {
+        let target = to.open(&self.config_path, &self.profile.name);
+        if !migrate_credentials(self.store.as_ref(), target.as_ref())? {
+            return Ok(false);
+        }
+
+        self.store = target;
+        self.profile.credential_store = to;
+        Self::save_profile(&self.config_path, &self.profile)?;
+        Ok(true)
+    }
+
+    /// Resolves the config path: `--config`, then `INOREADER_CONFIG`,
+    /// then `$XDG_CONFIG_HOME/inoreader/config` (`~/.config/inoreader/config`).
+    pub fn resolve_config_path(cli_config: Option<&str>) -> String {
+        let path = Self::config_path_from(
+            cli_config,
+            env::var("INOREADER_CONFIG").ok().as_deref(),
+            env::var("XDG_CONFIG_HOME").ok().as_deref(),
+            env::var("HOME").ok().as_deref(),
+        );
+        path.to_string_lossy().to_string()
+    }
+
+    fn config_path_from(
+        cli_config: Option<&str>,
+        env_config: Option<&str>,
+        xdg_config_home: Option<&str>,
+        home: Option<&str>,
+    ) -> PathBuf {
+        if let Some(path) = cli_config.or(env_config).filter(|path| !path.is_empty()) {
+            return PathBuf::from(path);
+        }
+
+        // XDG Base Directory: 空や相対パスの XDG_CONFIG_HOME は無視する
+        let config_home = match xdg_config_home.filter(|dir| Path::new(dir).is_absolute()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(home.unwrap_or(".")).join(".config"),
+        };
+        config_home.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME)
+    }
+
+    /// Validates the config file and rewrites it as TOML if it still uses the `Key:Value`
+    /// format. Returns `true` when the file was converted.
+    pub fn upgrade_config_format(config_path: &str) -> Result<bool> {
+        let path = Path::new(config_path);
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let mut config_file = ConfigFile::parse(&contents)?;
+        if !is_legacy_format(&contents) {
+            return Ok(false);
+        }
+
+        config_file.save(path)?;
+        Ok(true)
+    }
+
+    /// Moves tokens saved by older versions in `./.config` to the `default` profile, once.
+    /// Returns `true` when something was migrated.
+    pub fn migrate_legacy_config(config_path: &str) -> Result<bool> {
+        Self::migrate_legacy_file(
+            LEGACY_CONFIG_FILE_NAME,
+            config_path,
+            CredentialStoreKind::from_env,
+        )
+    }
+
+    /// Moves the tokens in the legacy file at `legacy_path` to the store of the `default`
+    /// profile. The store is only opened when the file holds tokens, so that normal runs do
+    /// not ask for a passphrase or call the keyring.
+    fn migrate_legacy_file(
+        legacy_path: &str,
+        config_path: &str,
+        target_kind: impl FnOnce() -> Result<CredentialStoreKind, ConfigError>,
+    ) -> Result<bool> {
+        if !fs::read_to_string(legacy_path).is_ok_and(|contents| contents.contains("RefreshToken:"))
+        {
+            return Ok(false);
+        }
+
+        let target = target_kind()?.open(config_path, DEFAULT_PROFILE);
+        if target.exists()? {
+            return Ok(false);
+        }
+
+        let legacy = CredentialStoreKind::File.open(legacy_path, DEFAULT_PROFILE);
+        Ok(migrate_credentials(legacy.as_ref(), target.as_ref())?)
+    }
 
+    pub fn save_config(&self, config: &Config) -> Result<()> {
+        Ok(self.store.save(config)?)
+    }
+
+    /// Removes the saved tokens of this profile, keeping its settings.
+    pub fn delete_tokens(&mut self) -> Result<()> {
+        self.store.delete()?;
+        self.tokens = None;
         Ok(())
     }
 
+    pub fn get_credential_store_name(&self) -> &'static str {
+        self.store.name()
+    }
+
+    pub fn get_config_path(&self) -> &str {
+        &self.config_path
+    }
+
+    pub fn get_profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn has_saved_tokens(&self) -> bool {
+        self.tokens.is_some()
+    }
+
     pub fn get_saved_access_token(&self) -> String {
-        self.access_token.to_string()
+        self.tokens
+            .as_ref()
+            .map(|tokens| tokens.access_token.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn get_saved_expires_in(&self) -> u64 {
+        self.tokens.as_ref().map_or(0, |tokens| tokens.expires_in)
+    }
+
+    pub fn get_saved_refresh_token(&self) -> String {
+        self.tokens
+            .as_ref()
+            .map(|tokens| tokens.refresh_token.clone())
+            .unwrap_or_default()
     }
 
-    pub fn is_token_expired(&self) -> bool {
-        let current_time = SystemTime::now();
-        let since_the_epoch = current_time
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        let current_time_in_seconds = since_the_epoch.as_secs();
+    pub fn get_saved_scope(&self) -> Scope {
+        self.tokens
+            .as_ref()
+            .and_then(|tokens| tokens.scope.parse::<Scope>().ok())
+            .unwrap_or(Scope::Read)
+    }
 
-        if current_time_in_seconds > self.expires_in {
-            return true;
+    /// Checks that the saved token was granted `required` before a command touches the account.
+    pub fn require_scope(&self, required: Scope) -> Result<()> {
+        let granted = self.get_saved_scope();
+        if granted.includes(required) {
+            return Ok(());
         }
-        false
+        Err(AuthenticationError::InsufficientScope { granted, required }.into())
     }
 
-    pub fn get_saved_refresh_token(&self) -> String {
-        self.refresh_token.to_string()
+    /// Fails with `NotAuthenticated` when the profile has no saved tokens.
+    pub fn require_tokens(&self) -> Result<()> {
+        match self.has_saved_tokens() {
+            true => Ok(()),
+            false => Err(AuthenticationError::NotAuthenticated {
+                profile: self.profile.name.clone(),
+            }
+            .into()),
+        }
     }
 
     pub fn get_saved_state(&self) -> String {
-        self.state.to_string()
+        self.tokens
+            .as_ref()
+            .map(|tokens| tokens.state.clone())
+            .unwrap_or_default()
     }
 
     pub fn get_saved_authorization_code(&self) -> String {
@@ -101,3 +677,205 @@ impl ConfigRepository {
         String::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_path_from() {
+        assert_eq!(
+            ConfigRepository::config_path_from(
+                Some("/cli/config"),
+                Some("/env/config"),
+                Some("/xdg"),
+                Some("/home/user")
+            ),
+            PathBuf::from("/cli/config")
+        );
+        assert_eq!(
+            ConfigRepository::config_path_from(
+                None,
+                Some("/env/config"),
+                Some("/xdg"),
+                Some("/home/user")
+            ),
+            PathBuf::from("/env/config")
+        );
+        assert_eq!(
+            ConfigRepository::config_path_from(None, None, Some("/xdg"), Some("/home/user")),
+            PathBuf::from("/xdg/inoreader/config")
+        );
+        assert_eq!(
+            ConfigRepository::config_path_from(None, None, Some("relative"), Some("/home/user")),
+            PathBuf::from("/home/user/.config/inoreader/config")
+        );
+    }
+
+    #[test]
+    fn test_legacy_config_format() {
+        let config_file = ConfigFile::parse(
+            "DefaultProfile:team\nState:abc\nAccessToken:access\nRefreshToken:refresh\nExpiresIn:42\n\n[team]\nClientId:team-id\nOutputFormat:json\n",
+        )
+        .unwrap();
+
+        assert_eq!(config_file.version, CONFIG_VERSION);
+        assert_eq!(config_file.default_profile.as_deref(), Some("team"));
+        let tokens = config_file.profiles[DEFAULT_PROFILE]
+            .tokens
+            .as_ref()
+            .unwrap();
+        assert_eq!(tokens.refresh_token, "refresh");
+        assert_eq!(tokens.expires_in, 42);
+        assert_eq!(tokens.scope, "read");
+        let team = &config_file.profiles["team"];
+        assert_eq!(team.client_id.as_deref(), Some("team-id"));
+        assert_eq!(team.output_format, Some(OutputFormat::Json));
+
+        let tokens =
+            parse_tokens("AccessToken:access\nRefreshToken:refresh\nExpiresIn:42\n").unwrap();
+        assert_eq!(
+            parse_tokens(&serialize_tokens(&tokens).unwrap()),
+            Ok(tokens)
+        );
+    }
+
+    #[test]
+    fn test_legacy_migration_opens_no_store_without_a_legacy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_path = dir.path().join(".config");
+        let legacy_path = legacy_path.to_str().unwrap();
+        let config_path = dir.path().join("inoreader/config");
+        let config_path = config_path.to_str().unwrap();
+        let no_store = || -> Result<CredentialStoreKind, ConfigError> {
+            panic!("a credential store was opened")
+        };
+
+        assert!(
+            !ConfigRepository::migrate_legacy_file(legacy_path, config_path, no_store).unwrap()
+        );
+        fs::write(legacy_path, "ClientId:abc\n").unwrap();
+        assert!(
+            !ConfigRepository::migrate_legacy_file(legacy_path, config_path, no_store).unwrap()
+        );
+
+        fs::write(
+            legacy_path,
+            "AccessToken:access\nRefreshToken:refresh\nExpiresIn:42\n",
+        )
+        .unwrap();
+        assert!(
+            ConfigRepository::migrate_legacy_file(legacy_path, config_path, || {
+                Ok(CredentialStoreKind::File)
+            })
+            .unwrap()
+        );
+        let target = CredentialStoreKind::File.open(config_path, DEFAULT_PROFILE);
+        assert_eq!(target.load().unwrap().unwrap().refresh_token, "refresh");
+        assert!(!Path::new(legacy_path).exists());
+    }
+
+    #[test]
+    fn test_config_errors_name_the_field() {
+        let field_of = |contents: &str| match ConfigFile::parse(contents) {
+            Err(ConfigError::InvalidField { field, .. }) => field,
+            other => panic!("unexpected result: {:?}", other),
+        };
+
+        assert_eq!(
+            field_of("[profiles.default.tokens]\naccess_token = \"a\"\nrefresh_token = \"r\"\nexpires_in = \"soon\"\n"),
+            "profiles.default.tokens.expires_in"
+        );
+        assert_eq!(
+            field_of("[profiles.default.tokens]\naccess_token = \"a\"\nrefresh_token = \"\"\nexpires_in = 1\n"),
+            "profiles.default.tokens.refresh_token"
+        );
+        assert_eq!(
+            field_of("[profiles.team]\ncredential_store = \"plaintext\"\n"),
+            "profiles.team.credential_store"
+        );
+        assert_eq!(
+            field_of("[profiles.team]\nclient_di = \"typo\"\n"),
+            "profiles.team.client_di"
+        );
+        assert_eq!(field_of("version = 99\n"), "version");
+        assert_eq!(
+            field_of("AccessToken:access\nRefreshToken:refresh\nExpiresIn:soon\n"),
+            "profiles.default.tokens.expires_in"
+        );
+        assert!(matches!(
+            ConfigFile::parse("version = "),
+            Err(ConfigError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn test_upgrade_config_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config");
+        fs::write(
+            &config_path,
+            "AccessToken:access\nRefreshToken:refresh\nExpiresIn:42\n",
+        )
+        .unwrap();
+        let config_path = config_path.to_str().unwrap();
+
+        assert!(ConfigRepository::upgrade_config_format(config_path).unwrap());
+        assert!(!ConfigRepository::upgrade_config_format(config_path).unwrap());
+
+        let contents = fs::read_to_string(config_path).unwrap();
+        assert!(contents.starts_with("version = 1"));
+        assert!(contents.contains("[profiles.default.tokens]"));
+        let repository = ConfigRepository::new(config_path, DEFAULT_PROFILE).unwrap();
+        assert_eq!(repository.get_saved_refresh_token(), "refresh");
+    }
+
+    #[test]
+    fn test_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config");
+        let config_path = config_path.to_str().unwrap();
+
+        let mut personal = Profile::new("personal", CredentialStoreKind::File);
+        personal.client_id = Some("personal-id".to_string());
+        let mut team = Profile::new("team", CredentialStoreKind::File);
+        team.output_format = OutputFormat::Json;
+        ConfigRepository::save_profile(config_path, &personal).unwrap();
+        ConfigRepository::save_profile(config_path, &team).unwrap();
+        ConfigRepository::set_default_profile(config_path, "team").unwrap();
+        assert!(ConfigRepository::set_default_profile(config_path, "missing").is_err());
+
+        let (profiles, default) = ConfigRepository::list_profiles(config_path).unwrap();
+        assert_eq!(profiles, vec![personal.clone(), team.clone()]);
+        assert_eq!(default, "team");
+        assert_eq!(profiles[1].output_path(), "articles.json");
+        assert_eq!(
+            ConfigRepository::resolve_profile_name(config_path, Some("personal")),
+            "personal"
+        );
+
+        let repository = ConfigRepository::new(config_path, "personal").unwrap();
+        repository
+            .save_config(&Config {
+                authorization_code: String::new(),
+                state: "state".to_string(),
+                access_token: "access".to_string(),
+                refresh_token: "refresh".to_string(),
+                expires_in: 42,
+                scope: "read".to_string(),
+            })
+            .unwrap();
+        let repository = ConfigRepository::new(config_path, "personal").unwrap();
+        assert_eq!(repository.get_profile(), &personal);
+        assert_eq!(repository.get_saved_refresh_token(), "refresh");
+        assert!(!ConfigRepository::new(config_path, "team")
+            .unwrap()
+            .has_saved_tokens());
+
+        assert!(ConfigRepository::remove_profile(config_path, "team").unwrap());
+        assert!(!ConfigRepository::remove_profile(config_path, "team").unwrap());
+        let (profiles, default) = ConfigRepository::list_profiles(config_path).unwrap();
+        assert_eq!(profiles, vec![personal]);
+        assert_eq!(default, DEFAULT_PROFILE);
+    }
+}
@@ -0,0 +1,572 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use rand::RngCore;
+
+use crate::domain::{Config, CredentialStoreKind};
+use crate::repositories::config_repository::{
+    parse_tokens, serialize_tokens, ConfigError, ConfigFile, DEFAULT_PROFILE,
+};
+
+const KEYRING_SERVICE: &str = "inoreader_house_cleaning";
+const KEYRING_CLIENT_SECRET_SERVICE: &str = "inoreader_house_cleaning:client_secret";
+const ENCRYPTED_FILE_MAGIC: &[u8] = b"INOREADER-ENC1";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+/// Where the tokens and the client secret of a profile are kept.
+pub trait CredentialStore {
+    fn name(&self) -> &'static str;
+    fn load(&self) -> io::Result<Option<Config>>;
+    fn save(&self, tokens: &Config) -> io::Result<()>;
+    /// Deletes the tokens. The client secret is kept.
+    fn delete(&self) -> io::Result<()>;
+    fn load_client_secret(&self) -> io::Result<Option<String>>;
+    fn save_client_secret(&self, client_secret: &str) -> io::Result<()>;
+    fn delete_client_secret(&self) -> io::Result<()>;
+
+    /// Whether the store holds credentials, without decrypting them.
+    fn exists(&self) -> io::Result<bool> {
+        Ok(self.load()?.is_some())
+    }
+}
+
+impl CredentialStoreKind {
+    /// Reads `INOREADER_CREDENTIAL_STORE`, defaulting to the plaintext file.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        match std::env::var("INOREADER_CREDENTIAL_STORE") {
+            Ok(kind) => kind.parse().map_err(|message| ConfigError::InvalidField {
+                field: "INOREADER_CREDENTIAL_STORE".to_string(),
+                message,
+            }),
+            Err(_) => Ok(CredentialStoreKind::File),
+        }
+    }
+
+    /// Opens the store holding the tokens of `profile`. The `default` profile keeps
+    /// the locations used before profiles existed.
+    pub fn open(&self, config_path: &str, profile: &str) -> Box<dyn CredentialStore> {
+        let is_default = profile == DEFAULT_PROFILE;
+        match self {
+            CredentialStoreKind::File => Box::new(FileCredentialStore::new(config_path, profile)),
+            CredentialStoreKind::Keyring if is_default => {
+                Box::new(KeyringCredentialStore::new(config_path))
+            }
+            CredentialStoreKind::Keyring => Box::new(KeyringCredentialStore::new(&format!(
+                "{}#{}",
+                config_path, profile
+            ))),
+            CredentialStoreKind::Encrypted => {
+                let path = if is_default {
+                    format!("{}.enc", config_path)
+                } else {
+                    format!("{}.{}.enc", config_path, profile)
+                };
+                Box::new(EncryptedFileCredentialStore::asking(&path))
+            }
+        }
+    }
+}
+
+// 1 回の実行で何度もパスフレーズを聞かないようにキャッシュする
+fn session_passphrase(confirm: bool) -> io::Result<&'static str> {
+    static PASSPHRASE: OnceLock<String> = OnceLock::new();
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase);
+    }
+
+    let passphrase = match std::env::var("INOREADER_CREDENTIAL_PASSPHRASE") {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let passphrase = rpassword::prompt_password("Credential passphrase: ")?;
+            // 打ち間違えたまま暗号化すると誰も読めなくなる
+            if confirm && rpassword::prompt_password("Repeat the passphrase: ")? != passphrase {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the passphrases did not match",
+                ));
+            }
+            passphrase
+        }
+    };
+    Ok(PASSPHRASE.get_or_init(|| passphrase))
+}
+
+/// Replaces `path` with `contents`, readable and writable by the owner only. The file is
+/// written next to it and renamed over it, so a crash leaves either the old or the new file.
+pub(crate) fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    // 前回の残りは別の mode で作られているかもしれない
+    remove_if_exists(Path::new(&temp_path))?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+
+    // Windows ではディレクトリを開いて同期できない
+    if cfg!(unix) {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Keeps the tokens in the `tokens` table of the profile in the config file.
+pub struct FileCredentialStore {
+    path: PathBuf,
+    profile: String,
+}
+
+impl FileCredentialStore {
+    pub fn new(path: &str, profile: &str) -> Self {
+        FileCredentialStore {
+            path: PathBuf::from(path),
+            profile: profile.to_string(),
+        }
+    }
+}
+
+impl CredentialStore for FileCredentialStore {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn load(&self) -> io::Result<Option<Config>> {
+        let config_file = ConfigFile::load(&self.path)?;
+        Ok(config_file
+            .profiles
+            .get(&self.profile)
+            .and_then(|section| section.tokens.clone()))
+    }
+
+    fn save(&self, tokens: &Config) -> io::Result<()> {
+        let mut config_file = ConfigFile::load(&self.path)?;
+        config_file
+            .profiles
+            .entry(self.profile.clone())
+            .or_default()
+            .tokens = Some(tokens.clone());
+        config_file.save(&self.path)
+    }
+
+    fn delete(&self) -> io::Result<()> {
+        let mut config_file = ConfigFile::load(&self.path)?;
+        if let Some(section) = config_file.profiles.get_mut(&self.profile) {
+            section.tokens = None;
+        }
+        config_file.save(&self.path)
+    }
+
+    fn load_client_secret(&self) -> io::Result<Option<String>> {
+        let config_file = ConfigFile::load(&self.path)?;
+        Ok(config_file
+            .profiles
+            .get(&self.profile)
+            .and_then(|section| section.client_secret.clone()))
+    }
+
+    fn save_client_secret(&self, client_secret: &str) -> io::Result<()> {
+        let mut config_file = ConfigFile::load(&self.path)?;
+        config_file
+            .profiles
+            .entry(self.profile.clone())
+            .or_default()
+            .client_secret = Some(client_secret.to_string());
+        config_file.save(&self.path)
+    }
+
+    fn delete_client_secret(&self) -> io::Result<()> {
+        let mut config_file = ConfigFile::load(&self.path)?;
+        if let Some(section) = config_file.profiles.get_mut(&self.profile) {
+            section.client_secret = None;
+        }
+        config_file.save(&self.path)
+    }
+}
+
+pub struct KeyringCredentialStore {
+    account: String,
+}
+
+impl KeyringCredentialStore {
+    pub fn new(account: &str) -> Self {
+        KeyringCredentialStore {
+            account: account.to_string(),
+        }
+    }
+
+    fn entry(&self, service: &str) -> io::Result<keyring::Entry> {
+        keyring::Entry::new(service, &self.account).map_err(io::Error::other)
+    }
+
+    fn get(&self, service: &str) -> io::Result<Option<String>> {
+        match self.entry(service)?.get_password() {
+            Ok(contents) => Ok(Some(contents)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    fn remove(&self, service: &str) -> io::Result<()> {
+        match self.entry(service)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+}
+
+impl CredentialStore for KeyringCredentialStore {
+    fn name(&self) -> &'static str {
+        "keyring"
+    }
+
+    fn load(&self) -> io::Result<Option<Config>> {
+        match self.get(KEYRING_SERVICE)? {
+            Some(contents) => Ok(Some(parse_tokens(&contents)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, tokens: &Config) -> io::Result<()> {
+        self.entry(KEYRING_SERVICE)?
+            .set_password(&serialize_tokens(tokens)?)
+            .map_err(io::Error::other)
+    }
+
+    fn delete(&self) -> io::Result<()> {
+        self.remove(KEYRING_SERVICE)
+    }
+
+    fn load_client_secret(&self) -> io::Result<Option<String>> {
+        self.get(KEYRING_CLIENT_SECRET_SERVICE)
+    }
+
+    fn save_client_secret(&self, client_secret: &str) -> io::Result<()> {
+        self.entry(KEYRING_CLIENT_SECRET_SERVICE)?
+            .set_password(client_secret)
+            .map_err(io::Error::other)
+    }
+
+    fn delete_client_secret(&self) -> io::Result<()> {
+        self.remove(KEYRING_CLIENT_SECRET_SERVICE)
+    }
+}
+
+/// File layout: magic | salt (16 bytes) | nonce (12 bytes) | AES-256-GCM ciphertext.
+/// The client secret is kept the same way in a second file, `<path>.secret`.
+pub struct EncryptedFileCredentialStore {
+    path: PathBuf,
+    /// `None` to take it from `INOREADER_CREDENTIAL_PASSPHRASE` or the terminal when first needed
+    passphrase: Option<String>,
+}
+
+impl EncryptedFileCredentialStore {
+    pub fn new(path: &str, passphrase: &str) -> Self {
+        EncryptedFileCredentialStore {
+            path: PathBuf::from(path),
+            passphrase: Some(passphrase.to_string()),
+        }
+    }
+
+    /// Asks for the passphrase on the first read or write, twice when creating the files.
+    pub fn asking(path: &str) -> Self {
+        EncryptedFileCredentialStore {
+            path: PathBuf::from(path),
+            passphrase: None,
+        }
+    }
+
+    fn client_secret_path(&self) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(".secret");
+        PathBuf::from(path)
+    }
+
+    fn cipher(&self, salt: &[u8], creating: bool) -> io::Result<Aes256Gcm> {
+        let passphrase = match &self.passphrase {
+            Some(passphrase) => passphrase,
+            None => session_passphrase(creating)?,
+        };
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Option<String>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(path)?;
+        let header_length = ENCRYPTED_FILE_MAGIC.len() + SALT_LENGTH + NONCE_LENGTH;
+        if data.len() < header_length || !data.starts_with(ENCRYPTED_FILE_MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an encrypted credential file",
+            ));
+        }
+
+        let (salt, rest) = data[ENCRYPTED_FILE_MAGIC.len()..].split_at(SALT_LENGTH);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+        let plaintext = self
+            .cipher(salt, false)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "failed to decrypt credentials (wrong passphrase?)",
+                )
+            })?;
+
+        String::from_utf8(plaintext)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn write(&self, path: &Path, contents: &str) -> io::Result<()> {
+        // このプロファイルで初めて暗号化するときだけ確認入力を求める
+        let creating = !self.path.exists() && !self.client_secret_path().exists();
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher(&salt, creating)?
+            .encrypt(&nonce, contents.as_bytes())
+            .map_err(|_| io::Error::other("failed to encrypt credentials"))?;
+
+        let mut data = ENCRYPTED_FILE_MAGIC.to_vec();
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        write_private_file(path, &data)
+    }
+}
+
+impl CredentialStore for EncryptedFileCredentialStore {
+    fn name(&self) -> &'static str {
+        "encrypted"
+    }
+
+    fn load(&self) -> io::Result<Option<Config>> {
+        match self.read(&self.path)? {
+            Some(contents) => Ok(Some(parse_tokens(&contents)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, tokens: &Config) -> io::Result<()> {
+        self.write(&self.path, &serialize_tokens(tokens)?)
+    }
+
+    fn delete(&self) -> io::Result<()> {
+        remove_if_exists(&self.path)
+    }
+
+    fn exists(&self) -> io::Result<bool> {
+        Ok(self.path.exists())
+    }
+
+    fn load_client_secret(&self) -> io::Result<Option<String>> {
+        self.read(&self.client_secret_path())
+    }
+
+    fn save_client_secret(&self, client_secret: &str) -> io::Result<()> {
+        self.write(&self.client_secret_path(), client_secret)
+    }
+
+    fn delete_client_secret(&self) -> io::Result<()> {
+        remove_if_exists(&self.client_secret_path())
+    }
+}
+
+/// Copies the tokens and the client secret from `from` to `to`, checks the copy and then
+/// removes them from the source. Returns `false` when `from` holds neither.
+pub fn migrate_credentials(
+    from: &dyn CredentialStore,
+    to: &dyn CredentialStore,
+) -> io::Result<bool> {
+    let tokens = from.load()?;
+    let client_secret = from.load_client_secret()?;
+    if tokens.is_none() && client_secret.is_none() {
+        return Ok(false);
+    }
+
+    let unreadable = || {
+        io::Error::other(format!(
+            "credentials written to the {} store could not be read back",
+            to.name()
+        ))
+    };
+    if let Some(tokens) = &tokens {
+        to.save(tokens)?;
+        if to.load()?.as_ref() != Some(tokens) {
+            return Err(unreadable());
+        }
+    }
+    if let Some(client_secret) = &client_secret {
+        to.save_client_secret(client_secret)?;
+        if to.load_client_secret()?.as_ref() != Some(client_secret) {
+            return Err(unreadable());
+        }
+    }
+    if tokens.is_some() {
+        from.delete()?;
+    }
+    if client_secret.is_some() {
+        from.delete_client_secret()?;
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(refresh_token: &str) -> Config {
+        Config {
+            authorization_code: String::new(),
+            state: "state".to_string(),
+            access_token: "access".to_string(),
+            refresh_token: refresh_token.to_string(),
+            expires_in: 42,
+            scope: "read".to_string(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_store_creates_owner_only_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        let store = FileCredentialStore::new(path.to_str().unwrap(), "default");
+        let other = FileCredentialStore::new(path.to_str().unwrap(), "team");
+
+        assert_eq!(store.load().unwrap(), None);
+        store.save(&tokens("refresh")).unwrap();
+        other.save(&tokens("other")).unwrap();
+        assert_eq!(store.load().unwrap(), Some(tokens("refresh")));
+        assert_eq!(other.load().unwrap(), Some(tokens("other")));
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // 緩いパーミッションの既存ファイルも置き換えで 0600 になる
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        store.save(&tokens("rotated")).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(store.load().unwrap(), Some(tokens("rotated")));
+        assert!(!dir.path().join("config.tmp").exists());
+
+        store.delete().unwrap();
+        assert_eq!(store.load().unwrap(), None);
+        other.delete().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_encrypted_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.enc");
+        let path = path.to_str().unwrap();
+
+        EncryptedFileCredentialStore::new(path, "correct horse")
+            .save(&tokens("refresh"))
+            .unwrap();
+
+        let raw = fs::read(path).unwrap();
+        assert!(!String::from_utf8_lossy(&raw).contains("access_token"));
+
+        let loaded = EncryptedFileCredentialStore::new(path, "correct horse")
+            .load()
+            .unwrap();
+        assert_eq!(loaded, Some(tokens("refresh")));
+
+        let wrong = EncryptedFileCredentialStore::new(path, "battery staple").load();
+        assert_eq!(wrong.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_migrate_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain_path = dir.path().join("config");
+        let encrypted_path = dir.path().join("config.enc");
+        let plain = FileCredentialStore::new(plain_path.to_str().unwrap(), "default");
+        let encrypted =
+            EncryptedFileCredentialStore::new(encrypted_path.to_str().unwrap(), "passphrase");
+
+        assert!(!migrate_credentials(&plain, &encrypted).unwrap());
+
+        plain.save(&tokens("refresh")).unwrap();
+        assert!(migrate_credentials(&plain, &encrypted).unwrap());
+
+        assert!(!plain_path.exists());
+        assert_eq!(encrypted.load().unwrap(), Some(tokens("refresh")));
+    }
+
+    #[test]
+    fn test_client_secret_is_kept_apart_from_the_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain_path = dir.path().join("config");
+        let encrypted_path = dir.path().join("config.enc");
+        let plain = FileCredentialStore::new(plain_path.to_str().unwrap(), "default");
+        let encrypted =
+            EncryptedFileCredentialStore::new(encrypted_path.to_str().unwrap(), "passphrase");
+
+        // まだトークンのないプロファイルでもクライアントシークレットは移せる
+        plain.save_client_secret("s3cret").unwrap();
+        assert!(migrate_credentials(&plain, &encrypted).unwrap());
+        assert!(!plain_path.exists());
+        assert_eq!(encrypted.load().unwrap(), None);
+        assert_eq!(
+            encrypted.load_client_secret().unwrap().as_deref(),
+            Some("s3cret")
+        );
+        let raw = fs::read(dir.path().join("config.enc.secret")).unwrap();
+        assert!(!String::from_utf8_lossy(&raw).contains("s3cret"));
+
+        encrypted.save(&tokens("refresh")).unwrap();
+        encrypted.delete().unwrap();
+        assert_eq!(encrypted.load().unwrap(), None);
+        assert!(encrypted.load_client_secret().unwrap().is_some());
+        encrypted.delete_client_secret().unwrap();
+        assert_eq!(encrypted.load_client_secret().unwrap(), None);
+    }
+
+    #[test]
+    fn test_credential_store_kind_from_str() {
+        assert_eq!("file".parse(), Ok(CredentialStoreKind::File));
+        assert_eq!("keyring".parse(), Ok(CredentialStoreKind::Keyring));
+        assert_eq!("encrypted".parse(), Ok(CredentialStoreKind::Encrypted));
+        assert!("plaintext".parse::<CredentialStoreKind>().is_err());
+    }
+}
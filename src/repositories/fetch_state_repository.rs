@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+const FETCH_STATE_FILE_NAME: &str = "fetch_state.json";
+
+/// Position of a fetch that stopped before the end of its stream.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FetchState {
+    pub continuation: String,
+    /// Items saved by the runs so far
+    pub fetched: usize,
+}
+
+/// Saved positions for `fetch --resume`, keyed by profile, stream and query.
+pub struct FetchStateRepository {
+    path: PathBuf,
+}
+
+impl FetchStateRepository {
+    /// Keeps the positions next to the config file.
+    pub fn new(config_path: &str) -> Self {
+        FetchStateRepository {
+            path: Path::new(config_path).with_file_name(FETCH_STATE_FILE_NAME),
+        }
+    }
+
+    pub fn load(&self, key: &str) -> io::Result<Option<FetchState>> {
+        Ok(self.load_all()?.remove(key))
+    }
+
+    pub fn save(&self, key: &str, state: &FetchState) -> io::Result<()> {
+        let mut states = self.load_all()?;
+        states.insert(key.to_string(), state.clone());
+        self.save_all(&states)
+    }
+
+    pub fn clear(&self, key: &str) -> io::Result<()> {
+        let mut states = self.load_all()?;
+        if states.remove(key).is_some() {
+            self.save_all(&states)?;
+        }
+        Ok(())
+    }
+
+    fn load_all(&self) -> io::Result<BTreeMap<String, FetchState>> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save_all(&self, states: &BTreeMap<String, FetchState>) -> io::Result<()> {
+        if states.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        }
+        if let Some(parent) = self
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(states)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config");
+        let repository = FetchStateRepository::new(config_path.to_str().unwrap());
+        let state = FetchState {
+            continuation: "gmMZgKmmqI4U".to_string(),
+            fetched: 1000,
+        };
+
+        assert_eq!(repository.load("default:starred").unwrap(), None);
+        repository.save("default:starred", &state).unwrap();
+        repository.save("team:starred", &state).unwrap();
+        assert_eq!(repository.load("default:starred").unwrap(), Some(state));
+        assert!(dir.path().join(FETCH_STATE_FILE_NAME).exists());
+
+        repository.clear("default:starred").unwrap();
+        assert_eq!(repository.load("default:starred").unwrap(), None);
+        repository.clear("team:starred").unwrap();
+        assert!(!dir.path().join(FETCH_STATE_FILE_NAME).exists());
+    }
+}
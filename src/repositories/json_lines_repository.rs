@@ -0,0 +1,126 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Append-only file of one JSON value per line, as used by the journals.
+///
+/// A crash while appending can leave the last line incomplete. `load` skips such a line without
+/// touching the file, and the next `append` cuts it off before writing, so that every entry
+/// starts on its own line.
+pub struct JsonLinesRepository {
+    path: PathBuf,
+}
+
+impl JsonLinesRepository {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonLinesRepository { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads every complete entry; an empty list when the file does not exist.
+    pub fn load<T: DeserializeOwned>(&self) -> io::Result<Vec<T>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        // 追記中に落ちた最後の行は読み飛ばす
+        let complete = &contents[..contents.rfind('\n').map_or(0, |end| end + 1)];
+        complete
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+            .collect()
+    }
+
+    /// Appends the entries and returns once they are on disk.
+    pub fn append<T: Serialize>(&self, entries: &[T]) -> io::Result<()> {
+        if let Some(parent) = self
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        Self::drop_torn_line(&mut file)?;
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()
+    }
+
+    /// Deletes the file.
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Cuts off a last line left incomplete by a crash.
+    fn drop_torn_line(file: &mut fs::File) -> io::Result<()> {
+        let len = file.metadata()?.len();
+        if len == 0 {
+            return Ok(());
+        }
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] == b'\n' {
+            return Ok(());
+        }
+
+        let mut contents = Vec::with_capacity(len as usize);
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut contents)?;
+        let complete = contents
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |end| end + 1);
+        file.set_len(complete as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_torn_last_line_is_skipped_then_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = JsonLinesRepository::new(dir.path().join("journal.jsonl"));
+        assert_eq!(repository.load::<u32>().unwrap(), Vec::<u32>::new());
+
+        repository.append(&[1, 2]).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(repository.path())
+            .unwrap();
+        file.write_all(b"12").unwrap();
+        let torn = fs::read(repository.path()).unwrap();
+
+        assert_eq!(repository.load::<u32>().unwrap(), vec![1, 2]);
+        assert_eq!(fs::read(repository.path()).unwrap(), torn);
+
+        repository.append(&[3]).unwrap();
+        assert_eq!(repository.load::<u32>().unwrap(), vec![1, 2, 3]);
+
+        repository.clear().unwrap();
+        assert!(!repository.path().exists());
+        repository.clear().unwrap();
+    }
+}
@@ -1,2 +1,7 @@
+pub mod archive_journal_repository;
 pub mod articles_data_repository;
 pub mod config_repository;
+pub mod credential_store_repository;
+pub mod fetch_state_repository;
+pub mod json_lines_repository;
+pub mod undo_journal_repository;
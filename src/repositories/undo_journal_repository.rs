@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::infrastructure::TagChange;
+use crate::repositories::json_lines_repository::JsonLinesRepository;
+
+/// One invocation of a command that changes the account, e.g. an `unstar`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UndoRun {
+    pub id: String,
+    pub command: String,
+    /// The run an `undo` reverts
+    pub undoes: Option<String>,
+}
+
+impl UndoRun {
+    pub fn new(command: &str) -> Self {
+        UndoRun {
+            id: format!(
+                "{}-{:04x}",
+                Local::now().format("%Y%m%d-%H%M%S"),
+                rand::random::<u16>()
+            ),
+            command: command.to_string(),
+            undoes: None,
+        }
+    }
+
+    /// The run that reverts `run`.
+    pub fn undoing(run: &str) -> Self {
+        UndoRun {
+            undoes: Some(run.to_string()),
+            ..UndoRun::new("undo")
+        }
+    }
+}
+
+/// A tag change of one item, or of a whole stream for `mark-all-as-read`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mutation {
+    pub run: String,
+    pub command: String,
+    /// Unix time (seconds)
+    pub at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<String>,
+    /// Stream marked as read as a whole; its items are not known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removed: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<String>,
+}
+
+impl Mutation {
+    /// The change that reverts this one, if it was made to a known item.
+    pub fn inverse(&self) -> Option<(String, TagChange)> {
+        let parse = |tag: &Option<String>| tag.as_deref().and_then(|tag| tag.parse().ok());
+        let item = self.item.clone()?;
+        Some((
+            item,
+            TagChange {
+                add: parse(&self.removed),
+                remove: parse(&self.added),
+            },
+        ))
+    }
+}
+
+/// Mutations of one run, as listed by `undo`.
+#[derive(Debug, PartialEq)]
+pub struct RunSummary {
+    pub id: String,
+    pub command: String,
+    pub at: i64,
+    /// Items with a recorded change; only these can be reverted
+    pub items: usize,
+    /// Streams marked as read as a whole
+    pub streams: Vec<String>,
+    pub undone: bool,
+}
+
+/// Append-only journal of every tag change made with a profile, kept next to the config file,
+/// so that `undo` can revert a run.
+pub struct UndoJournalRepository {
+    journal: JsonLinesRepository,
+}
+
+impl UndoJournalRepository {
+    pub fn new(config_path: &str, profile: &str) -> Self {
+        UndoJournalRepository {
+            journal: JsonLinesRepository::new(
+                Path::new(config_path).with_file_name(format!("undo_journal_{}.jsonl", profile)),
+            ),
+        }
+    }
+
+    /// Records `change` of the items, after it was sent.
+    pub fn record(&self, run: &UndoRun, change: &TagChange, item_ids: &[String]) -> io::Result<()> {
+        let at = Local::now().timestamp();
+        let mutations: Vec<Mutation> = item_ids
+            .iter()
+            .map(|id| Mutation {
+                run: run.id.clone(),
+                command: run.command.clone(),
+                at,
+                item: Some(id.clone()),
+                stream: None,
+                added: change.add.as_ref().map(|tag| tag.as_id()),
+                removed: change.remove.as_ref().map(|tag| tag.as_id()),
+                undoes: run.undoes.clone(),
+            })
+            .collect();
+        self.journal.append(&mutations)
+    }
+
+    /// Records that every item of `stream` was marked as read.
+    pub fn record_stream_read(&self, run: &UndoRun, stream: &str, read: &str) -> io::Result<()> {
+        self.journal.append(&[Mutation {
+            run: run.id.clone(),
+            command: run.command.clone(),
+            at: Local::now().timestamp(),
+            item: None,
+            stream: Some(stream.to_string()),
+            added: Some(read.to_string()),
+            removed: None,
+            undoes: run.undoes.clone(),
+        }])
+    }
+
+    pub fn load(&self) -> io::Result<Vec<Mutation>> {
+        self.journal.load()
+    }
+
+    /// The mutations of `run`, in the order they were made.
+    pub fn run(&self, run: &str) -> io::Result<Vec<Mutation>> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .filter(|mutation| mutation.run == run)
+            .collect())
+    }
+
+    /// The runs, oldest first.
+    pub fn runs(&self) -> io::Result<Vec<RunSummary>> {
+        let mutations = self.load()?;
+        let undone: HashSet<&str> = mutations
+            .iter()
+            .filter_map(|mutation| mutation.undoes.as_deref())
+            .collect();
+
+        let mut runs: Vec<RunSummary> = Vec::new();
+        for mutation in &mutations {
+            if runs.last().is_none_or(|run| run.id != mutation.run) {
+                runs.push(RunSummary {
+                    id: mutation.run.clone(),
+                    command: mutation.command.clone(),
+                    at: mutation.at,
+                    items: 0,
+                    streams: Vec::new(),
+                    undone: undone.contains(mutation.run.as_str()),
+                });
+            }
+            let run = runs.last_mut().unwrap();
+            match (&mutation.item, &mutation.stream) {
+                (Some(_), _) => run.items += 1,
+                (None, Some(stream)) => run.streams.push(stream.clone()),
+                (None, None) => {}
+            }
+        }
+        Ok(runs)
+    }
+
+    /// The newest run that is not an `undo`, has not been undone and changed known items.
+    pub fn last_undoable_run(&self) -> io::Result<Option<String>> {
+        Ok(self
+            .runs()?
+            .into_iter()
+            .rev()
+            .find(|run| run.command != "undo" && !run.undone && run.items > 0)
+            .map(|run| run.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::StreamId;
+
+    #[test]
+    fn test_runs_and_inverse() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config");
+        let repository = UndoJournalRepository::new(config_path.to_str().unwrap(), "default");
+        let unstar = UndoRun::new("unstar");
+        let mark_read = UndoRun::new("mark-read");
+        let unstar_change = TagChange::remove(StreamId::Starred);
+
+        assert_eq!(repository.last_undoable_run().unwrap(), None);
+        repository
+            .record(&unstar, &unstar_change, &["a".to_string(), "b".to_string()])
+            .unwrap();
+        repository
+            .record_stream_read(
+                &mark_read,
+                "user/-/label/Tech",
+                "user/-/state/com.google/read",
+            )
+            .unwrap();
+        // 記事の分からない mark-all-as-read は戻せない
+        assert_eq!(
+            repository.last_undoable_run().unwrap(),
+            Some(unstar.id.clone())
+        );
+
+        let undo_unstar = UndoRun::undoing(&unstar.id);
+        repository
+            .record(
+                &undo_unstar,
+                &TagChange::add(StreamId::Starred),
+                &["a".to_string()],
+            )
+            .unwrap();
+
+        let runs = repository.runs().unwrap();
+        assert_eq!(runs.len(), 3);
+        assert_eq!((runs[0].items, runs[0].undone), (2, true));
+        assert_eq!(runs[1].items, 0);
+        assert_eq!(runs[1].streams, vec!["user/-/label/Tech"]);
+        assert_eq!(runs[2].command, "undo");
+        assert_eq!(repository.last_undoable_run().unwrap(), None);
+
+        let mutations = repository.run(&unstar.id).unwrap();
+        assert_eq!(
+            mutations[1].inverse(),
+            Some(("b".to_string(), TagChange::add(StreamId::Starred)))
+        );
+        let stream_read = repository.run(&mark_read.id).unwrap();
+        assert_eq!(stream_read[0].inverse(), None);
+    }
+}
@@ -1,17 +1,139 @@
+use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use rand::distributions::Alphanumeric;
 use rand::Rng;
 
-use crate::domain::{Config, TokenResponse};
+use crate::domain::{Config, Scope, TokenResponse};
+use crate::error::Error;
+use crate::infrastructure::base_url_from_env;
 use crate::repositories::config_repository::ConfigRepository;
+use crate::services::callback_listener_service::{CallbackListener, CallbackParams};
+use crate::services::pkce_service::Pkce;
+use crate::services::token_service::TOKEN_PATH;
+use url::Url;
 
-pub struct AuthenticationService;
+pub struct AuthenticationService {
+    base_url: String,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    config_path: String,
+    profile: String,
+    scope: Scope,
+    manual: bool,
+    use_pkce: bool,
+}
+
+#[derive(Debug)]
+pub enum AuthenticationError {
+    Io(std::io::Error),
+    InvalidRedirectUri(String),
+    Denied(String),
+    MissingCode,
+    MissingState,
+    StateMismatch,
+    NotAuthenticated { profile: String },
+    InsufficientScope { granted: Scope, required: Scope },
+}
 
-const AUTH_URL: &str = "https://www.inoreader.com/oauth2/auth";
-const TOKEN_URL: &str = "https://www.inoreader.com/oauth2/token";
+impl fmt::Display for AuthenticationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthenticationError::Io(e) => write!(f, "I/O error: {}", e),
+            AuthenticationError::InvalidRedirectUri(e) => write!(f, "invalid redirect URI: {}", e),
+            AuthenticationError::Denied(e) => write!(f, "authorization was denied: {}", e),
+            AuthenticationError::MissingCode => write!(f, "authorization code was not found"),
+            AuthenticationError::MissingState => {
+                write!(f, "state parameter was not found in the redirect")
+            }
+            // 期待値は秘密なので表示しない
+            AuthenticationError::StateMismatch => write!(
+                f,
+                "state parameter did not match the authorization request; possible CSRF attempt"
+            ),
+            AuthenticationError::NotAuthenticated { profile } => write!(
+                f,
+                "no saved token for profile '{}'; run 'setup' first",
+                profile
+            ),
+            AuthenticationError::InsufficientScope { granted, required } => write!(
+                f,
+                "the saved token only has the '{}' scope, but this command needs '{}'; run 'reauth --scope write' first",
+                granted, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AuthenticationError {}
+
+impl From<std::io::Error> for AuthenticationError {
+    fn from(e: std::io::Error) -> Self {
+        AuthenticationError::Io(e)
+    }
+}
+
+const AUTH_PATH: &str = "/oauth2/auth";
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// An authorization in progress: the URL to open and what the redirect has to match.
+pub struct AuthorizationRequest {
+    pub url: String,
+    state: String,
+    pkce: Option<Pkce>,
+}
+
+impl AuthorizationRequest {
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+}
 
 impl AuthenticationService {
+    pub fn new(
+        client_id: &str,
+        client_secret: &str,
+        redirect_uri: &str,
+        config_path: &str,
+        profile: &str,
+    ) -> Self {
+        AuthenticationService {
+            base_url: base_url_from_env(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            config_path: config_path.to_string(),
+            profile: profile.to_string(),
+            scope: Scope::Read,
+            manual: false,
+            use_pkce: false,
+        }
+    }
+
+    /// Sends the authorization and token requests to `base_url` instead of Inoreader.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Paste the redirect URL instead of starting the local callback listener.
+    pub fn with_manual_entry(mut self, manual: bool) -> Self {
+        self.manual = manual;
+        self
+    }
+
+    /// Add a PKCE (S256) code challenge to the authorization request.
+    pub fn with_pkce(mut self, use_pkce: bool) -> Self {
+        self.use_pkce = use_pkce;
+        self
+    }
+
     fn generate_random_state() -> String {
         rand::thread_rng()
             .sample_iter(&Alphanumeric)
@@ -20,46 +142,113 @@ impl AuthenticationService {
             .collect()
     }
 
+    fn build_auth_url(&self, state: &str, pkce: Option<&Pkce>) -> String {
+        let mut url = Url::parse(&format!("{}{}", self.base_url, AUTH_PATH))
+            .expect("authorization URL is valid");
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("response_type", "code")
+            .append_pair("scope", self.scope.as_str())
+            .append_pair("state", state);
+
+        if let Some(pkce) = pkce {
+            url.query_pairs_mut()
+                .append_pair("code_challenge", &pkce.challenge)
+                .append_pair("code_challenge_method", Pkce::METHOD);
+        }
+
+        url.to_string()
+    }
+
     async fn get_access_token(
-        client_id: &str,
-        client_secret: &str,
-        redirect_uri: &str,
+        &self,
         code: &str,
-    ) -> Result<TokenResponse, reqwest::Error> {
+        code_verifier: Option<&str>,
+    ) -> Result<TokenResponse, Error> {
+        let mut params = vec![
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("code", code),
+            ("grant_type", "authorization_code"),
+        ];
+        if let Some(code_verifier) = code_verifier {
+            params.push(("code_verifier", code_verifier));
+        }
+
         let response = reqwest::Client::new()
-            .post(TOKEN_URL)
-            .form(&[
-                ("client_id", client_id),
-                ("client_secret", client_secret),
-                ("redirect_uri", redirect_uri),
-                ("code", code),
-                ("grant_type", "authorization_code"),
-            ])
+            .post(format!("{}{}", self.base_url, TOKEN_PATH))
+            .form(&params)
             .send()
             .await?;
+        if !response.status().is_success() {
+            return Err(Error::from_response(response).await);
+        }
 
-        response.json::<TokenResponse>().await
+        Ok(serde_json::from_str(&response.text().await?)?)
     }
 
-    pub async fn app_authenticate(
-        client_id: &str,
-        client_secret: &str,
-        redirect_uri: &str,
-    ) -> Result<(), reqwest::Error> {
+    /// Starts an authorization: a fresh state (and PKCE verifier) and the URL to open.
+    pub fn authorization_request(&self) -> AuthorizationRequest {
         let state: String = Self::generate_random_state();
-        let auth_url: String = format!(
-            "{}?client_id={}&redirect_uri={}&response_type=code&scope=read&state={}",
-            AUTH_URL, client_id, redirect_uri, state
-        );
-        println!("Please navigate to: {}", auth_url);
+        let pkce: Option<Pkce> = self.use_pkce.then(Pkce::generate);
+        AuthorizationRequest {
+            url: self.build_auth_url(&state, pkce.as_ref()),
+            state,
+            pkce,
+        }
+    }
 
-        println!("Enter the code from the URL here: ");
-        let mut code: String = String::new();
-        std::io::stdin().read_line(&mut code).unwrap();
-        let code: &str = code.trim();
+    pub async fn app_authenticate(&self) -> Result<(), Error> {
+        let request = self.authorization_request();
+        println!("Please navigate to: {}", request.url);
 
-        let token_response: TokenResponse =
-            Self::get_access_token(client_id, client_secret, redirect_uri, code).await?;
+        let callback: CallbackParams = if self.manual {
+            Self::read_code_from_stdin()?
+        } else {
+            match CallbackListener::bind(&self.redirect_uri).await {
+                Ok(listener) => {
+                    println!(
+                        "Waiting for the redirect on http://{} ...",
+                        listener.local_addr()?
+                    );
+                    tokio::time::timeout(
+                        CALLBACK_TIMEOUT,
+                        listener.wait_for_callback(&request.state),
+                    )
+                    .await
+                    .map_err(|_| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            "timed out waiting for the redirect",
+                        )
+                    })??
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Could not start the callback listener ({}). Falling back to manual entry.",
+                        e
+                    );
+                    Self::read_code_from_stdin()?
+                }
+            }
+        };
+        self.complete_authorization(&request, &callback).await
+    }
+
+    /// Checks the redirect against `request`, exchanges its code for tokens and saves them.
+    pub async fn complete_authorization(
+        &self,
+        request: &AuthorizationRequest,
+        callback: &CallbackParams,
+    ) -> Result<(), Error> {
+        let state: &str = &request.state;
+        callback.verify_state(state)?;
+        let code: &str = &callback.code;
+
+        let code_verifier = request.pkce.as_ref().map(|pkce| pkce.verifier.as_str());
+        let token_response: TokenResponse = self.get_access_token(code, code_verifier).await?;
 
         let expiry_time = Self::calculate_expiry_time(token_response.expires_in);
 
@@ -70,14 +259,25 @@ impl AuthenticationService {
             access_token: token_response.access_token,
             refresh_token: token_response.refresh_token,
             expires_in: expiry_time,
+            scope: token_response
+                .scope
+                .unwrap_or_else(|| self.scope.as_str().to_string()),
         };
 
-        let config_repository = ConfigRepository::new(".config");
-        let _result = config_repository.save_config(&app_authorization_info);
+        ConfigRepository::new(&self.config_path, &self.profile)?
+            .save_config(&app_authorization_info)?;
 
         Ok(())
     }
 
+    // ヘッドレス環境向け: ブラウザのアドレスバーからリダイレクト URL を貼り付けてもらう
+    fn read_code_from_stdin() -> Result<CallbackParams, AuthenticationError> {
+        println!("Paste the full URL you were redirected to (or its query string) here: ");
+        let mut input: String = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        CallbackParams::from_redirect_input(&input)
+    }
+
     fn calculate_expiry_time(expires_in_seconds: u64) -> u64 {
         let current_time: SystemTime = SystemTime::now();
         let since_the_epoch = current_time
@@ -108,4 +308,60 @@ mod tests {
 
         assert_eq!(expiry_time - current_time, 3600);
     }
+
+    #[test]
+    fn test_build_auth_url() {
+        let service = AuthenticationService::new(
+            "client-id",
+            "client-secret",
+            "http://localhost:8080/callback",
+            "config",
+            "default",
+        );
+        let pkce = Pkce::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+
+        let without_pkce = service.build_auth_url("the-state", None);
+        assert!(without_pkce.contains("redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcallback"));
+        assert!(without_pkce.contains("state=the-state"));
+        assert!(without_pkce.contains("scope=read&"));
+        assert!(!without_pkce.contains("code_challenge"));
+
+        let service = service.with_scope(Scope::ReadWrite);
+        let with_pkce = service.build_auth_url("the-state", Some(&pkce));
+        assert!(with_pkce.contains("scope=read+write&"));
+        assert!(with_pkce.contains("code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"));
+        assert!(with_pkce.contains("code_challenge_method=S256"));
+    }
+
+    #[test]
+    fn test_verify_state_accepts_matching_state() {
+        let callback = CallbackParams::from_redirect_input(
+            "http://localhost:8080/callback?code=the-code&state=expected-state",
+        )
+        .unwrap();
+
+        assert!(callback.verify_state("expected-state").is_ok());
+    }
+
+    #[test]
+    fn test_verify_state_rejects_mismatch() {
+        let callback = CallbackParams::from_redirect_input(
+            "http://localhost:8080/callback?code=the-code&state=forged-state",
+        )
+        .unwrap();
+
+        let e = callback.verify_state("expected-state").unwrap_err();
+        assert!(matches!(e, AuthenticationError::StateMismatch));
+        assert!(!e.to_string().contains("expected-state"));
+    }
+
+    #[test]
+    fn test_verify_state_rejects_missing_state() {
+        let callback = CallbackParams::from_redirect_input("code=the-code").unwrap();
+
+        assert!(matches!(
+            callback.verify_state("expected-state"),
+            Err(AuthenticationError::MissingState)
+        ));
+    }
 }
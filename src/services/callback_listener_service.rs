@@ -0,0 +1,310 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use url::Url;
+
+use crate::services::authentication_service::AuthenticationError;
+
+/// How long a connection may take to send its request line and headers.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SUCCESS_PAGE: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Inoreader for Rust</title></head>\
+<body><h1>Authentication complete</h1><p>You can close this window and return to the terminal.</p></body></html>";
+const FAILURE_PAGE: &str =
+    "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Inoreader for Rust</title></head>\
+<body><h1>Authentication failed</h1><p>Please check the terminal for details.</p></body></html>";
+
+/// `code` and `state` extracted from the OAuth redirect.
+#[derive(Debug, PartialEq)]
+pub struct CallbackParams {
+    pub code: String,
+    pub state: Option<String>,
+}
+
+impl CallbackParams {
+    /// Parses the query part of the redirect (`code=...&state=...`).
+    pub fn from_query(query: &str) -> Result<Self, AuthenticationError> {
+        let mut code = None;
+        let mut state = None;
+        let mut error = None;
+
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "code" => code = Some(value.into_owned()),
+                "state" => state = Some(value.into_owned()),
+                "error" => error = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        if let Some(error) = error {
+            return Err(AuthenticationError::Denied(error));
+        }
+
+        match code {
+            Some(code) if !code.is_empty() => Ok(CallbackParams { code, state }),
+            _ => Err(AuthenticationError::MissingCode),
+        }
+    }
+
+    /// Checks that the redirect carries the state of the authorization request.
+    pub fn verify_state(&self, expected: &str) -> Result<(), AuthenticationError> {
+        match self.state.as_deref() {
+            None => Err(AuthenticationError::MissingState),
+            Some(actual) if actual != expected => Err(AuthenticationError::StateMismatch),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Parses what the user pasted: the full redirect URL or just its query
+    /// (`code=...&state=...`, with or without the leading `?`).
+    pub fn from_redirect_input(input: &str) -> Result<Self, AuthenticationError> {
+        let input = input.trim();
+        match Url::parse(input) {
+            Ok(url) => Self::from_query(url.query().unwrap_or("")),
+            Err(_) => Self::from_query(input.trim_start_matches('?')),
+        }
+    }
+}
+
+/// Loopback HTTP listener that receives the OAuth redirect from the browser.
+pub struct CallbackListener {
+    listener: TcpListener,
+    path: String,
+}
+
+impl CallbackListener {
+    /// Binds the host and port of `redirect_uri`. Only loopback addresses are accepted.
+    pub async fn bind(redirect_uri: &str) -> Result<Self, AuthenticationError> {
+        let url = Url::parse(redirect_uri)
+            .map_err(|e| AuthenticationError::InvalidRedirectUri(e.to_string()))?;
+
+        let host = match url.host_str() {
+            Some("localhost") | Some("127.0.0.1") => "127.0.0.1",
+            Some("[::1]") => "::1",
+            _ => {
+                return Err(AuthenticationError::InvalidRedirectUri(format!(
+                    "{} is not a loopback address",
+                    redirect_uri
+                )))
+            }
+        };
+        let port = url.port_or_known_default().unwrap_or(80);
+
+        let listener = TcpListener::bind((host, port)).await?;
+
+        Ok(CallbackListener {
+            listener,
+            path: url.path().to_string(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, AuthenticationError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Waits until the browser hits the redirect path and returns its parameters once their
+    /// state matches `expected_state`. Requests to other paths (e.g. `/favicon.ico`) are
+    /// answered with 404 and ignored.
+    pub async fn wait_for_callback(
+        &self,
+        expected_state: &str,
+    ) -> Result<CallbackParams, AuthenticationError> {
+        // ブラウザは何も送らない接続を先に張ることがあるので、接続ごとに読む
+        let mut requests = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (mut stream, _) = accepted?;
+                    requests.spawn(async move {
+                        let target = tokio::time::timeout(
+                            REQUEST_TIMEOUT,
+                            Self::read_request_target(&mut stream),
+                        )
+                        .await;
+                        (stream, target)
+                    });
+                }
+                Some(Ok((mut stream, target))) = requests.join_next() => {
+                    let target = match target {
+                        Ok(Ok(target)) => target,
+                        _ => continue,
+                    };
+
+                    let (path, query) = target.split_once('?').unwrap_or((target.as_str(), ""));
+                    if path != self.path {
+                        Self::respond(&mut stream, "404 Not Found", "Not Found").await?;
+                        continue;
+                    }
+
+                    let params = CallbackParams::from_query(query)
+                        .and_then(|params| params.verify_state(expected_state).map(|_| params));
+                    match params {
+                        Ok(_) => Self::respond(&mut stream, "200 OK", SUCCESS_PAGE).await?,
+                        Err(_) => {
+                            Self::respond(&mut stream, "400 Bad Request", FAILURE_PAGE).await?
+                        }
+                    }
+                    return params;
+                }
+            }
+        }
+    }
+
+    async fn read_request_target(stream: &mut TcpStream) -> std::io::Result<String> {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+
+        // ヘッダーは読み捨てる
+        let mut header = String::new();
+        while reader.read_line(&mut header).await? > 0 && header.trim_end() != "" {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("GET"), Some(target)) => Ok(target.to_string()),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unexpected request",
+            )),
+        }
+    }
+
+    async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn browser_request(addr: SocketAddr, target: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, addr);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn test_from_query() {
+        let params = CallbackParams::from_query("code=abc%2F123&state=xyz").unwrap();
+        assert_eq!(params.code, "abc/123");
+        assert_eq!(params.state.as_deref(), Some("xyz"));
+
+        assert!(matches!(
+            CallbackParams::from_query("error=access_denied&state=xyz"),
+            Err(AuthenticationError::Denied(_))
+        ));
+        assert!(matches!(
+            CallbackParams::from_query("state=xyz"),
+            Err(AuthenticationError::MissingCode)
+        ));
+    }
+
+    #[test]
+    fn test_from_redirect_input() {
+        let expected = CallbackParams {
+            code: "the-code".to_string(),
+            state: Some("the-state".to_string()),
+        };
+
+        for input in [
+            "http://localhost:8080/callback?code=the-code&state=the-state",
+            "?code=the-code&state=the-state",
+            "  code=the-code&state=the-state\n",
+        ] {
+            assert_eq!(
+                CallbackParams::from_redirect_input(input).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_callback() {
+        let listener = CallbackListener::bind("http://127.0.0.1:0/callback")
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let browser = tokio::spawn(async move {
+            let not_found = browser_request(addr, "/favicon.ico").await;
+            let success = browser_request(addr, "/callback?code=the-code&state=the-state").await;
+            (not_found, success)
+        });
+
+        let params = listener.wait_for_callback("the-state").await.unwrap();
+        let (not_found, success) = browser.await.unwrap();
+
+        assert_eq!(params.code, "the-code");
+        assert_eq!(params.state.as_deref(), Some("the-state"));
+        assert!(not_found.starts_with("HTTP/1.1 404"));
+        assert!(success.starts_with("HTTP/1.1 200"));
+        assert!(success.contains("Authentication complete"));
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_does_not_block_the_callback() {
+        let listener = CallbackListener::bind("http://127.0.0.1:0/callback")
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let browser = tokio::spawn(async move {
+            let _preconnect = TcpStream::connect(addr).await.unwrap();
+            browser_request(addr, "/callback?code=the-code&state=the-state").await
+        });
+
+        let params =
+            tokio::time::timeout(REQUEST_TIMEOUT / 2, listener.wait_for_callback("the-state"))
+                .await
+                .expect("the idle connection blocked the callback")
+                .unwrap();
+        assert_eq!(params.code, "the-code");
+        assert!(browser.await.unwrap().starts_with("HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_state_gets_the_failure_page() {
+        let listener = CallbackListener::bind("http://127.0.0.1:0/callback")
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let browser = tokio::spawn(async move {
+            browser_request(addr, "/callback?code=the-code&state=forged").await
+        });
+
+        let result = listener.wait_for_callback("the-state").await;
+        let response = browser.await.unwrap();
+
+        assert!(matches!(result, Err(AuthenticationError::StateMismatch)));
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.contains("Authentication failed"));
+    }
+
+    #[tokio::test]
+    async fn test_bind_rejects_non_loopback_host() {
+        let result = CallbackListener::bind("https://example.com/callback").await;
+        assert!(matches!(
+            result,
+            Err(AuthenticationError::InvalidRedirectUri(_))
+        ));
+    }
+}
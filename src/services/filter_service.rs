@@ -0,0 +1,747 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::domain::Item;
+use crate::infrastructure::StreamQuery;
+
+const TEXT_FIELDS: &str = "feed, title, author, url or label";
+const STATES: [&str; 4] = ["read", "unread", "starred", "annotated"];
+
+/// A `--where` expression, e.g. `feed ~ "arstechnica" and age > 90d and not label:keep`.
+///
+/// ```text
+/// expr       := term ("or" term)*
+/// term       := factor ("and" factor)*
+/// factor     := "not" factor | "(" expr ")" | condition
+/// condition  := field op value | "label:" name | "is:" state
+/// ```
+///
+/// Text fields (`feed`, `title`, `author`, `url`, `label`) take `~` (contains), `!~`, `=` and
+/// `!=`, all case-insensitive. `age` (`90d`, `12h`, ...) and `published` (a date, RFC 3339,
+/// Unix time or age) take `<`, `<=`, `>` and `>=`. States are read, unread, starred and
+/// annotated.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Text {
+        field: TextField,
+        op: TextOp,
+        /// Lowercased
+        value: String,
+    },
+    Time {
+        field: TimeField,
+        op: TimeOp,
+        /// Seconds for `age`, Unix time for `published`
+        value: i64,
+    },
+    State(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextField {
+    Feed,
+    Title,
+    Author,
+    Url,
+    Label,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextOp {
+    Contains,
+    NotContains,
+    Equals,
+    NotEquals,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeField {
+    Age,
+    Published,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeOp {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Why an expression could not be parsed, and where.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterError {
+    pub message: String,
+    /// Character offset in `input`
+    pub position: usize,
+    pub input: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} at column {}", self.message, self.position + 1)?;
+        writeln!(f, "  {}", self.input)?;
+        write!(f, "  {}^", " ".repeat(self.position))
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl Filter {
+    /// Whether `item` matches, with ages counted back from `now` (Unix time).
+    pub fn matches(&self, item: &Item, now: i64) -> bool {
+        match self {
+            Filter::And(left, right) => left.matches(item, now) && right.matches(item, now),
+            Filter::Or(left, right) => left.matches(item, now) || right.matches(item, now),
+            Filter::Not(filter) => !filter.matches(item, now),
+            Filter::Text { field, op, value } => {
+                let values = Self::text_values(item, *field);
+                let hit = |op: TextOp| {
+                    values.iter().any(|candidate| {
+                        let candidate = candidate.to_lowercase();
+                        match op {
+                            TextOp::Equals => candidate == *value,
+                            _ => candidate.contains(value.as_str()),
+                        }
+                    })
+                };
+                match op {
+                    TextOp::Contains => hit(TextOp::Contains),
+                    TextOp::Equals => hit(TextOp::Equals),
+                    TextOp::NotContains => !hit(TextOp::Contains),
+                    TextOp::NotEquals => !hit(TextOp::Equals),
+                }
+            }
+            Filter::Time { field, op, value } => {
+                // 日時の分からない記事はどの時間の条件にも合わない
+                let published = match Self::published(item) {
+                    Some(published) => published,
+                    None => return false,
+                };
+                let actual = match field {
+                    TimeField::Age => now - published,
+                    TimeField::Published => published,
+                };
+                match op {
+                    TimeOp::Less => actual < *value,
+                    TimeOp::LessOrEqual => actual <= *value,
+                    TimeOp::Greater => actual > *value,
+                    TimeOp::GreaterOrEqual => actual >= *value,
+                }
+            }
+            Filter::State(state) => match state.as_str() {
+                "unread" => !Self::has_state(item, "read"),
+                state => Self::has_state(item, state),
+            },
+        }
+    }
+
+    /// When the item was published, or else crawled by Inoreader. Both are missing on some
+    /// items, and then 0.
+    fn published(item: &Item) -> Option<i64> {
+        [item.published, item.crawl_time_msec / 1000]
+            .into_iter()
+            .find(|time| *time > 0)
+            .map(|time| time as i64)
+    }
+
+    fn text_values(item: &Item, field: TextField) -> Vec<&str> {
+        match field {
+            TextField::Feed => item
+                .origin
+                .iter()
+                .flat_map(|origin| {
+                    let url = origin.stream_id.strip_prefix("feed/");
+                    [
+                        Some(origin.title.as_str()),
+                        url,
+                        Some(origin.html_url.as_str()),
+                    ]
+                })
+                .flatten()
+                .collect(),
+            TextField::Title => vec![item.title.as_str()],
+            TextField::Author => item.author.as_deref().into_iter().collect(),
+            TextField::Url => item.url().into_iter().collect(),
+            TextField::Label => item.labels(),
+        }
+    }
+
+    fn has_state(item: &Item, state: &str) -> bool {
+        let suffix = format!("/state/com.google/{}", state);
+        item.categories
+            .iter()
+            .any(|category| category.ends_with(&suffix))
+    }
+}
+
+/// Writes the expression back in a canonical form that parses to the same filter, e.g. to
+/// tell apart the saved positions of `fetch --resume` runs with different expressions.
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::And(left, right) => write!(f, "({} and {})", left, right),
+            Filter::Or(left, right) => write!(f, "({} or {})", left, right),
+            Filter::Not(filter) => write!(f, "not {}", filter),
+            Filter::Text { field, op, value } => {
+                let field = match field {
+                    TextField::Feed => "feed",
+                    TextField::Title => "title",
+                    TextField::Author => "author",
+                    TextField::Url => "url",
+                    TextField::Label => "label",
+                };
+                let op = match op {
+                    TextOp::Contains => "~",
+                    TextOp::NotContains => "!~",
+                    TextOp::Equals => "=",
+                    TextOp::NotEquals => "!=",
+                };
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                write!(f, "{} {} \"{}\"", field, op, value)
+            }
+            Filter::Time { field, op, value } => {
+                let op = match op {
+                    TimeOp::Less => "<",
+                    TimeOp::LessOrEqual => "<=",
+                    TimeOp::Greater => ">",
+                    TimeOp::GreaterOrEqual => ">=",
+                };
+                match field {
+                    TimeField::Age => write!(f, "age {} {}s", op, value),
+                    TimeField::Published => write!(f, "published {} {}", op, value),
+                }
+            }
+            Filter::State(state) => write!(f, "is:{}", state),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            input: s,
+            tokens,
+            next: 0,
+        };
+        let filter = parser.expr()?;
+        match parser.peek() {
+            None => Ok(filter),
+            Some(token) => Err(parser.error_at(
+                token.position,
+                format!("expected 'and' or 'or' before {}", token.kind),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Word(word) => write!(f, "'{}'", word),
+            TokenKind::Str(s) => write!(f, "\"{}\"", s),
+            TokenKind::Op(op) => write!(f, "'{}'", op),
+            TokenKind::Open => write!(f, "'('"),
+            TokenKind::Close => write!(f, "')'"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+const OPERATORS: [&str; 8] = ["!~", "!=", "<=", ">=", "~", "=", "<", ">"];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, FilterError> {
+    let chars: Vec<char> = input.chars().collect();
+    let error = |message: &str, position| FilterError {
+        message: message.to_string(),
+        position,
+        input: input.to_string(),
+    };
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '(' {
+            i += 1;
+            TokenKind::Open
+        } else if c == ')' {
+            i += 1;
+            TokenKind::Close
+        } else if c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(error("unterminated string", start)),
+                    Some('"') => break,
+                    Some('\\') if matches!(chars.get(i + 1), Some('"' | '\\')) => {
+                        value.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(&c) => {
+                        value.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            TokenKind::Str(value)
+        } else if let Some(op) = OPERATORS.iter().find(|op| {
+            op.chars()
+                .enumerate()
+                .all(|(offset, c)| chars.get(i + offset) == Some(&c))
+        }) {
+            i += op.len();
+            TokenKind::Op(op)
+        } else if c == '!' {
+            return Err(error("expected '!=' or '!~'", start));
+        } else {
+            while i < chars.len() && !chars[i].is_whitespace() && !"()\"~=!<>".contains(chars[i]) {
+                i += 1;
+            }
+            TokenKind::Word(chars[start..i].iter().collect())
+        };
+        tokens.push(Token {
+            kind,
+            position: start,
+        });
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser<'_> {
+    fn expr(&mut self) -> Result<Filter, FilterError> {
+        let mut filter = self.term()?;
+        while self.eat_keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.term()?));
+        }
+        Ok(filter)
+    }
+
+    fn term(&mut self) -> Result<Filter, FilterError> {
+        let mut filter = self.factor()?;
+        while self.eat_keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.factor()?));
+        }
+        Ok(filter)
+    }
+
+    fn factor(&mut self) -> Result<Filter, FilterError> {
+        if self.eat_keyword("not") {
+            return Ok(Filter::Not(Box::new(self.factor()?)));
+        }
+        let token = match self.advance() {
+            Some(token) => token,
+            None => return Err(self.error_at_end("expected a condition")),
+        };
+        match token.kind {
+            TokenKind::Open => {
+                let filter = self.expr()?;
+                match self.advance() {
+                    Some(Token {
+                        kind: TokenKind::Close,
+                        ..
+                    }) => Ok(filter),
+                    Some(other) => Err(self.error_at(
+                        other.position,
+                        format!("expected ')' but found {}", other.kind),
+                    )),
+                    None => Err(self.error_at(token.position, "unclosed '('")),
+                }
+            }
+            TokenKind::Word(word) => self.condition(&word, token.position),
+            other => Err(self.error_at(
+                token.position,
+                format!("expected a condition but found {}", other),
+            )),
+        }
+    }
+
+    fn condition(&mut self, word: &str, position: usize) -> Result<Filter, FilterError> {
+        if let Some((prefix, rest)) = word.split_once(':') {
+            // label:"my tag" は word と文字列の 2 トークンになる
+            let value = match (rest, self.peek()) {
+                (
+                    "",
+                    Some(Token {
+                        kind: TokenKind::Str(value),
+                        ..
+                    }),
+                ) => {
+                    let value = value.clone();
+                    self.next += 1;
+                    value
+                }
+                ("", _) => {
+                    return Err(self.error_at(
+                        position + word.chars().count(),
+                        format!("expected a value after '{}'", word),
+                    ))
+                }
+                (rest, _) => rest.to_string(),
+            };
+            return match prefix.to_lowercase().as_str() {
+                "label" => Ok(Filter::Text {
+                    field: TextField::Label,
+                    op: TextOp::Equals,
+                    value: value.to_lowercase(),
+                }),
+                "is" if STATES.contains(&value.to_lowercase().as_str()) => {
+                    Ok(Filter::State(value.to_lowercase()))
+                }
+                "is" => Err(self.error_at(
+                    position + 3,
+                    format!("unknown state '{}' (expected {})", value, STATES.join(", ")),
+                )),
+                _ => Err(self.error_at(
+                    position,
+                    format!("unknown condition '{}:' (expected label: or is:)", prefix),
+                )),
+            };
+        }
+
+        let field = word.to_lowercase();
+        let text_field = match field.as_str() {
+            "feed" => Some(TextField::Feed),
+            "title" => Some(TextField::Title),
+            "author" => Some(TextField::Author),
+            "url" => Some(TextField::Url),
+            "label" => Some(TextField::Label),
+            "age" | "published" => None,
+            _ => {
+                return Err(self.error_at(
+                    position,
+                    format!(
+                        "unknown field '{}' (expected {}, age or published, or label:<name> or is:<state>)",
+                        word, TEXT_FIELDS
+                    ),
+                ))
+            }
+        };
+
+        let (op, op_position) = match self.advance() {
+            Some(Token {
+                kind: TokenKind::Op(op),
+                position,
+            }) => (op, position),
+            Some(other) => {
+                return Err(self.error_at(
+                    other.position,
+                    format!(
+                        "expected an operator after '{}' but found {}",
+                        word, other.kind
+                    ),
+                ))
+            }
+            None => {
+                return Err(self.error_at_end(&format!("expected an operator after '{}'", word)))
+            }
+        };
+        let (value, value_position) = match self.advance() {
+            Some(Token {
+                kind: TokenKind::Word(value) | TokenKind::Str(value),
+                position,
+            }) => (value, position),
+            Some(other) => {
+                return Err(self.error_at(
+                    other.position,
+                    format!("expected a value after '{}' but found {}", op, other.kind),
+                ))
+            }
+            None => return Err(self.error_at_end(&format!("expected a value after '{}'", op))),
+        };
+
+        if let Some(field) = text_field {
+            let op = match op {
+                "~" => TextOp::Contains,
+                "!~" => TextOp::NotContains,
+                "=" => TextOp::Equals,
+                "!=" => TextOp::NotEquals,
+                op => {
+                    return Err(self.error_at(
+                        op_position,
+                        format!("'{}' cannot be used with {}; use ~, !~, = or !=", op, word),
+                    ))
+                }
+            };
+            return Ok(Filter::Text {
+                field,
+                op,
+                value: value.to_lowercase(),
+            });
+        }
+
+        let op = match op {
+            "<" => TimeOp::Less,
+            "<=" => TimeOp::LessOrEqual,
+            ">" => TimeOp::Greater,
+            ">=" => TimeOp::GreaterOrEqual,
+            op => {
+                return Err(self.error_at(
+                    op_position,
+                    format!("'{}' cannot be used with {}; use <, <=, > or >=", op, word),
+                ))
+            }
+        };
+        let (field, value) = match field.as_str() {
+            "age" => match StreamQuery::parse_age(&value) {
+                Some(age) => (TimeField::Age, age),
+                None => {
+                    return Err(self.error_at(
+                        value_position,
+                        format!(
+                            "invalid age '{}' (expected a number and s, m, h, d or w, like 90d)",
+                            value
+                        ),
+                    ))
+                }
+            },
+            _ => match StreamQuery::parse_time(&value) {
+                Ok(time) => (TimeField::Published, time),
+                Err(message) => return Err(self.error_at(value_position, message)),
+            },
+        };
+        Ok(Filter::Time { field, op, value })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Word(word),
+                ..
+            }) if word.eq_ignore_ascii_case(keyword) => {
+                self.next += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn error_at(&self, position: usize, message: impl Into<String>) -> FilterError {
+        FilterError {
+            message: message.into(),
+            position,
+            input: self.input.to_string(),
+        }
+    }
+
+    fn error_at_end(&self, message: &str) -> FilterError {
+        self.error_at(self.input.chars().count(), message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_767_225_600; // 2026-01-01T00:00:00Z
+    const DAY: i64 = 86_400;
+
+    fn item(feed: &str, title: &str, age_days: i64, categories: &[&str]) -> Item {
+        serde_json::from_value(serde_json::json!({
+            "id": "tag:google.com,2005:reader/item/1",
+            "title": title,
+            "author": "Dan Goodin",
+            "published": NOW - age_days * DAY,
+            "categories": categories,
+            "canonical": [{ "href": format!("https://{}/article", feed) }],
+            "origin": {
+                "streamId": format!("feed/http://feeds.{}/rss", feed),
+                "title": feed,
+                "htmlUrl": format!("https://{}/", feed)
+            }
+        }))
+        .unwrap()
+    }
+
+    fn matches(expression: &str, item: &Item) -> bool {
+        expression.parse::<Filter>().unwrap().matches(item, NOW)
+    }
+
+    #[test]
+    fn test_example_expression() {
+        let expression = r#"feed ~ "arstechnica" and age > 90d and not label:keep"#;
+        let old = item("arstechnica.com", "Old", 120, &["user/1/label/Tech"]);
+        let kept = item("arstechnica.com", "Kept", 120, &["user/1/label/Keep"]);
+        let recent = item("arstechnica.com", "Recent", 10, &[]);
+        let other = item("example.com", "Other", 120, &[]);
+
+        assert!(matches(expression, &old));
+        assert!(!matches(expression, &kept));
+        assert!(!matches(expression, &recent));
+        assert!(!matches(expression, &other));
+    }
+
+    #[test]
+    fn test_text_operators() {
+        let item = item("arstechnica.com", "Rust 2.0 released", 1, &[]);
+
+        assert!(matches("title ~ rust", &item));
+        assert!(matches(r#"title = "RUST 2.0 RELEASED""#, &item));
+        assert!(matches("title !~ python", &item));
+        assert!(matches("author != \"someone else\"", &item));
+        assert!(matches("url ~ arstechnica.com/article", &item));
+        assert!(matches("feed = arstechnica.com", &item));
+        assert!(matches("feed ~ feeds.arstechnica", &item));
+        assert!(!matches("title = rust", &item));
+    }
+
+    #[test]
+    fn test_precedence_and_parentheses() {
+        let item = item("a.com", "Title", 1, &[]);
+
+        // not > and > or
+        assert!(matches("title ~ x and title ~ y or title ~ title", &item));
+        assert!(!matches(
+            "title ~ x and (title ~ y or title ~ title)",
+            &item
+        ));
+        assert!(matches("not title ~ x and title ~ t", &item));
+        assert!(matches("NOT (title ~ x OR title ~ y)", &item));
+    }
+
+    #[test]
+    fn test_time_and_state_conditions() {
+        let item = item(
+            "a.com",
+            "Title",
+            30,
+            &[
+                "user/1/state/com.google/read",
+                "user/1/state/com.google/starred",
+            ],
+        );
+
+        assert!(matches("age >= 30d and age < 5w", &item));
+        assert!(!matches("age > 30d", &item));
+        assert!(matches("published < 2025-12-15", &item));
+        assert!(matches("published > 1700000000", &item));
+        assert!(matches("is:read and is:starred and not is:unread", &item));
+        assert!(!matches("is:annotated", &item));
+        assert!(matches(r#"not label:"my tag""#, &item));
+    }
+
+    #[test]
+    fn test_time_conditions_without_a_publish_date() {
+        let mut undated = item("a.com", "Title", 0, &[]);
+        undated.published = 0;
+
+        assert!(!matches("age > 90d", &undated));
+        assert!(!matches("age < 90d", &undated));
+        assert!(!matches("published < 2025-12-15", &undated));
+        assert!(!matches("feed ~ a.com and age > 1d", &undated));
+
+        undated.crawl_time_msec = (NOW - 100 * DAY) as u64 * 1000;
+        assert!(matches("age > 90d", &undated));
+    }
+
+    #[test]
+    fn test_display_parses_to_the_same_filter() {
+        let expression =
+            r#"feed ~ "a \"b\" \\c" and (age > 90d or published <= 1700000000) and not is:read"#;
+        let filter: Filter = expression.parse().unwrap();
+        let canonical = filter.to_string();
+
+        assert_eq!(
+            canonical,
+            r#"((feed ~ "a \"b\" \\c" and (age > 7776000s or published <= 1700000000)) and not is:read)"#
+        );
+        assert_eq!(canonical.parse::<Filter>().unwrap(), filter);
+    }
+
+    fn error(expression: &str) -> FilterError {
+        expression.parse::<Filter>().unwrap_err()
+    }
+
+    #[test]
+    fn test_error_messages_point_at_the_problem() {
+        let e = error("feed ~ ars and age >");
+        assert_eq!(e.message, "expected a value after '>'");
+        assert_eq!(e.position, 20);
+        assert_eq!(
+            e.to_string(),
+            "expected a value after '>' at column 21\n  feed ~ ars and age >\n                      ^"
+        );
+
+        let e = error("colour = red");
+        assert!(e.message.starts_with("unknown field 'colour'"));
+        assert_eq!(e.position, 0);
+
+        let e = error("age ~ 90d");
+        assert_eq!(e.message, "'~' cannot be used with age; use <, <=, > or >=");
+        assert_eq!(e.position, 4);
+
+        let e = error("title > a");
+        assert_eq!(
+            e.message,
+            "'>' cannot be used with title; use ~, !~, = or !="
+        );
+
+        let e = error("age > 90 days");
+        assert!(e.message.starts_with("invalid age '90'"));
+        assert_eq!(e.position, 6);
+
+        let e = error("title ~ a title ~ b");
+        assert_eq!(e.message, "expected 'and' or 'or' before 'title'");
+        assert_eq!(e.position, 10);
+
+        let e = error("(title ~ a");
+        assert_eq!(e.message, "unclosed '('");
+        assert_eq!(e.position, 0);
+
+        let e = error(r#"title ~ "abc"#);
+        assert_eq!(e.message, "unterminated string");
+        assert_eq!(e.position, 8);
+
+        let e = error("is:new");
+        assert!(e.message.starts_with("unknown state 'new'"));
+
+        let e = error("title ~ a and");
+        assert_eq!(e.message, "expected a condition");
+        assert_eq!(e.position, 13);
+
+        let e = error("title ! a");
+        assert_eq!(e.message, "expected '!=' or '!~'");
+    }
+}
@@ -1,3 +1,6 @@
 pub mod authentication_service;
+pub mod callback_listener_service;
+pub mod filter_service;
+pub mod pkce_service;
 pub mod response_parser_service;
 pub mod token_service;
@@ -1,19 +1,56 @@
-use crate::domain::ApiResponse;
+use serde::Deserialize;
 
-type ParseResult = Result<(Vec<(String, String)>, Option<String>), serde_json::Error>;
+use crate::domain::{ApiResponse, Item, ItemIssue, ItemWarning};
+
+#[derive(Deserialize)]
+struct RawResponse {
+    #[serde(default)]
+    items: Vec<serde_json::Value>,
+    continuation: Option<String>,
+}
 
 pub struct ResponseParser;
 
 impl ResponseParser {
-    pub fn parse_response(json_str: &str) -> ParseResult {
-        let response: ApiResponse = serde_json::from_str(json_str)?;
-        let articles: Vec<(String, String)> = response
-            .items
-            .iter()
-            .map(|item| (item.title.clone(), item.canonical[0].href.clone()))
-            .collect();
+    /// Parses a page item by item. Only a page that is not a stream response at all is an
+    /// error; unreadable items are skipped and reported in `warnings`.
+    pub fn parse_page(json_str: &str) -> Result<ApiResponse, serde_json::Error> {
+        let raw: RawResponse = serde_json::from_str(json_str)?;
+        let mut page = ApiResponse {
+            items: Vec::with_capacity(raw.items.len()),
+            continuation: raw.continuation,
+            warnings: Vec::new(),
+        };
+
+        for (index, value) in raw.items.into_iter().enumerate() {
+            let id = value.get("id").and_then(|id| id.as_str()).map(String::from);
+            let warning = |issue| ItemWarning {
+                index,
+                id: id.clone(),
+                issue,
+            };
 
-        Ok((articles, response.continuation))
+            let item: Item = match serde_path_to_error::deserialize(value) {
+                Ok(item) => item,
+                Err(e) => {
+                    let message = match e.path().to_string().as_str() {
+                        "." => e.inner().to_string(),
+                        path => format!("`{}`: {}", path, e.inner()),
+                    };
+                    page.warnings.push(warning(ItemIssue::Skipped(message)));
+                    continue;
+                }
+            };
+            if item.title.is_empty() {
+                page.warnings.push(warning(ItemIssue::MissingTitle));
+            }
+            if item.url().is_none() {
+                page.warnings.push(warning(ItemIssue::MissingUrl));
+            }
+            page.items.push(item);
+        }
+
+        Ok(page)
     }
 }
 
@@ -92,12 +129,101 @@ mod tests {
             "continuation":"gmMZgKmmqI4U"
         }"#;
 
-        let (articles, _continuation) = ResponseParser::parse_response(json_str).unwrap();
+        let page = ResponseParser::parse_page(json_str).unwrap();
 
-        assert_eq!(articles.len(), 1);
+        assert_eq!(page.continuation.as_deref(), Some("gmMZgKmmqI4U"));
+        assert_eq!(page.items.len(), 1);
+        let item = &page.items[0];
         assert_eq!(
-            articles[0].0,
+            item.title,
             "Windows and Linux devices are under attack by a new cryptomining worm"
         );
+        assert_eq!(item.url(), Some("https://arstechnica.com/?p=1755573"));
+        assert!(page.warnings.is_empty());
+        assert_eq!(item.published, 1617969599);
+        assert_eq!(item.timestamp_usec, 1618211779000000);
+        assert_eq!(item.author.as_deref(), Some("Dan Goodin"));
+        assert_eq!(item.labels(), vec!["Tech"]);
+        assert_eq!(item.feed_title(), Some("Ars Technica » Gear & Gadgets"));
+        assert_eq!(item.alternate[0].media_type.as_deref(), Some("text/html"));
+        assert!(item.summary.as_ref().unwrap().content.starts_with("<div>"));
+        assert_eq!(item.annotations.len(), 1);
+        assert_eq!(item.annotations[0].note, "Check your firewall!");
+    }
+
+    #[test]
+    fn test_missing_canonical_falls_back_to_alternate() {
+        let page =
+            ResponseParser::parse_page(include_str!("../../tests/fixtures/missing_canonical.json"))
+                .unwrap();
+
+        assert_eq!(page.items[0].url(), Some("https://example.com/alternate"));
+        assert!(page.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_empty_canonical_falls_back_to_origin() {
+        let page =
+            ResponseParser::parse_page(include_str!("../../tests/fixtures/empty_canonical.json"))
+                .unwrap();
+
+        assert_eq!(page.items[0].url(), Some("https://example.com/"));
+        assert!(page.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_item_without_any_url_is_kept_with_warning() {
+        let page =
+            ResponseParser::parse_page(include_str!("../../tests/fixtures/no_url.json")).unwrap();
+
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].url(), None);
+        assert_eq!(
+            page.warnings,
+            vec![ItemWarning {
+                index: 0,
+                id: Some("tag:google.com,2005:reader/item/0000000000000003".to_string()),
+                issue: ItemIssue::MissingUrl,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_missing_or_null_title_is_empty() {
+        let page =
+            ResponseParser::parse_page(include_str!("../../tests/fixtures/missing_title.json"))
+                .unwrap();
+
+        assert_eq!(page.items.len(), 2);
+        assert!(page.items.iter().all(|item| item.title.is_empty()));
+        assert_eq!(page.warnings.len(), 2);
+        assert!(page
+            .warnings
+            .iter()
+            .all(|warning| warning.issue == ItemIssue::MissingTitle));
+    }
+
+    #[test]
+    fn test_malformed_items_are_skipped() {
+        let page =
+            ResponseParser::parse_page(include_str!("../../tests/fixtures/malformed_items.json"))
+                .unwrap();
+
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].title, "Valid");
+        assert_eq!(page.continuation.as_deref(), Some("next"));
+        let skipped: Vec<_> = page
+            .warnings
+            .iter()
+            .map(|w| (w.index, w.is_skipped()))
+            .collect();
+        assert_eq!(skipped, vec![(0, true), (1, true), (3, true)]);
+        assert!(page.warnings[0].to_string().contains("`published`"));
+    }
+
+    #[test]
+    fn test_non_stream_response_is_an_error() {
+        assert!(ResponseParser::parse_page("<html>Service Unavailable</html>").is_err());
+        assert!(ResponseParser::parse_page(r#"{"items": "none"}"#).is_err());
     }
 }
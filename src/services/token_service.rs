@@ -1,26 +1,50 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use reqwest::StatusCode;
+
 use crate::domain::{Config, TokenResponse};
+use crate::error::Error;
+use crate::infrastructure::base_url_from_env;
 use crate::repositories::config_repository::ConfigRepository;
 
+pub const TOKEN_PATH: &str = "/oauth2/token";
+
 pub struct TokenService {
+    token_url: String,
     client_id: String,
     client_secret: String,
     refresh_token: String,
+    config_path: String,
+    profile: String,
 }
 
 impl TokenService {
-    pub fn new(client_id: &str, client_secret: &str, refresh_token: &str) -> Self {
+    pub fn new(
+        client_id: &str,
+        client_secret: &str,
+        refresh_token: &str,
+        config_path: &str,
+        profile: &str,
+    ) -> Self {
         TokenService {
+            token_url: format!("{}{}", base_url_from_env(), TOKEN_PATH),
             client_id: client_id.to_string(),
             client_secret: client_secret.to_string(),
             refresh_token: refresh_token.to_string(),
+            config_path: config_path.to_string(),
+            profile: profile.to_string(),
         }
     }
 
-    async fn refresh_access_token(&self) -> Result<TokenResponse, reqwest::Error> {
+    /// Sends the token request to `base_url` instead of Inoreader.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.token_url = format!("{}{}", base_url.trim_end_matches('/'), TOKEN_PATH);
+        self
+    }
+
+    async fn refresh_access_token(&self) -> Result<TokenResponse, Error> {
         let response = reqwest::Client::new()
-            .post("https://www.inoreader.com/oauth2/token")
+            .post(&self.token_url)
             .form(&[
                 ("client_id", &self.client_id),
                 ("client_secret", &self.client_secret),
@@ -29,12 +53,23 @@ impl TokenService {
             ])
             .send()
             .await?;
+        // 失効・取り消し済みのリフレッシュトークンは invalid_grant (400) か 401 で拒否される
+        if matches!(
+            response.status(),
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED
+        ) {
+            return Err(Error::TokenExpired);
+        }
+        if !response.status().is_success() {
+            return Err(Error::from_response(response).await);
+        }
 
-        response.json::<TokenResponse>().await
+        Ok(serde_json::from_str(&response.text().await?)?)
     }
 
-    pub async fn refreshing_token(&self) -> Result<(), reqwest::Error> {
-        let config_repository = ConfigRepository::new(".config");
+    /// Refreshes the access token, saves it and returns the saved config.
+    pub async fn refreshing_token(&self) -> Result<Config, Error> {
+        let config_repository = ConfigRepository::new(&self.config_path, &self.profile)?;
         let token_response = self.refresh_access_token().await?;
 
         let expiry_time = Self::calculate_expiry_time(token_response.expires_in);
@@ -45,14 +80,14 @@ impl TokenService {
             access_token: token_response.access_token,
             refresh_token: token_response.refresh_token,
             expires_in: expiry_time,
+            scope: token_response
+                .scope
+                .unwrap_or_else(|| config_repository.get_saved_scope().as_str().to_string()),
         };
 
-        let config_repository = ConfigRepository::new(".config");
-        config_repository
-            .save_config(&config)
-            .expect("Failed to save token file");
+        config_repository.save_config(&config)?;
 
-        Ok(())
+        Ok(config)
     }
 
     fn calculate_expiry_time(expires_in_seconds: u64) -> u64 {